@@ -3,10 +3,13 @@
 /// 2. init wallet
 /// 3. load strategy
 /// 4. get one result
+use log::info;
 use std::error::Error;
 use std::path::{Path, PathBuf};
 
+use crate::calendar::TradingCalendar;
 use crate::models::AnalysisResult;
+use crate::universe::{Universe, DEFAULT_MARKETS};
 use crate::Config;
 
 pub fn run(config: &Config) -> Result<AnalysisResult, Box<dyn Error>> {
@@ -17,6 +20,16 @@ pub fn run(config: &Config) -> Result<AnalysisResult, Box<dyn Error>> {
             good: true,
         })
     } else {
+        let universe = data_universe(&data_dir);
+        info!("analysis data of {}", universe);
+        // data downloaded before the calendar was stored have no trade_cal
+        match TradingCalendar::load(&data_dir, "SSE") {
+            Ok(calendar) => info!(
+                "{} trading days",
+                calendar.trading_days_between(&config.data_start_date, &config.data_end_date)
+            ),
+            Err(e) => info!("no trading calendar: {}", e),
+        }
         Ok(AnalysisResult {
             finish: false,
             good: true,
@@ -33,16 +46,37 @@ pub fn check_data(date_dir: &PathBuf) -> bool {
     true
 }
 
+// data downloaded before the universe was recorded are always SSE and SZSE 主板
+pub fn data_universe(date_dir: &Path) -> Universe {
+    Universe::read(date_dir).unwrap_or_else(|| DEFAULT_MARKETS.parse().unwrap())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{DownloadType, Opt};
+    use crate::source::Source;
+    use crate::{AccountTier, DownloadType, ListStatus, Opt, RunMode};
 
     fn get_config() -> Config {
         let args = Opt {
             data_start_date: String::from("20210101"),
             data_end_date: String::from("20210922"),
             download_type: DownloadType::All,
+            run_mode: RunMode::Full,
+            tushare_api_url: None,
+            request_timeout: 30,
+            account_tier: AccountTier::Points5000,
+            rate_limit: vec![],
+            max_retries: 5,
+            concurrency: 8,
+            plan_only: false,
+            markets: "SSE:主板,SZSE:主板".parse().unwrap(),
+            list_status: vec![ListStatus::Listed],
+            index_codes: vec![String::from("000300.SH")],
+            source: Source::Tushare,
+            cassette: None,
+            strict: false,
+            command: None,
         };
         Config::new(args).unwrap()
     }
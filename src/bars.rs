@@ -0,0 +1,456 @@
+/// Daily bars of one stock in a date dir, raw or adjusted by adj factors
+/// raw prices drop on every ex-date of a split or dividend, adjusted ones don't:
+/// qfq (前复权) keeps prices of the latest day in data, history is price * adj / latest adj,
+/// hfq (后复权) keeps prices of the listing day, every day is price * adj.
+/// pct_chg, vol and amount are not adjusted.
+/// index bars in index_data are the same StockDaily, for benchmarks,
+/// so are fund bars in fund_data with factors in fund_adj_data, ETFs trade like stocks.
+/// dividends in dividend_data are applied by Wallet::on_trade_date on raw prices.
+/// money flow in moneyflow_data joins bars on (ts_code, trade_date).
+/// daily indicators in daily_basic_data are read as they are.
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::dataset::{self, read_as};
+use crate::models::{
+    FundBasic, IndexBasic, StockAdjFactor, StockDaily, StockDailyBasic, StockDividend,
+    StockMoneyflow,
+};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Adjust {
+    None,
+    Qfq,
+    Hfq,
+}
+
+impl FromStr for Adjust {
+    type Err = crate::ParseError;
+    fn from_str(adjust: &str) -> Result<Self, Self::Err> {
+        match adjust {
+            "none" => Ok(Adjust::None),
+            "qfq" => Ok(Adjust::Qfq),
+            "hfq" => Ok(Adjust::Hfq),
+            _ => Err("Could not parse adjust, should be none, qfq or hfq"),
+        }
+    }
+}
+
+// a list file like index_list, decoded like dataset records
+fn read_list<T: serde::de::DeserializeOwned>(file_name: &Path) -> Result<Vec<T>, Box<dyn Error>> {
+    let mut rows: Vec<T> = vec![];
+    for record in dataset::read_file(file_name)? {
+        rows.push(record.decode()?);
+    }
+    Ok(rows)
+}
+
+/// raw daily bars of one stock, sorted by trade_date
+pub fn read_daily(date_dir: &Path, ts_code: &str) -> Result<Vec<StockDaily>, Box<dyn Error>> {
+    read_as(date_dir, "daily", ts_code)
+}
+
+/// daily bars of one index, sorted by trade_date
+pub fn read_index_daily(date_dir: &Path, ts_code: &str) -> Result<Vec<StockDaily>, Box<dyn Error>> {
+    read_as(date_dir, "index_daily", ts_code)
+}
+
+/// indexes downloaded in date dir
+pub fn read_index_list(date_dir: &Path) -> Result<Vec<IndexBasic>, Box<dyn Error>> {
+    read_list(&date_dir.join("index_data").join("index_list"))
+}
+
+/// index bars from start_date to end_date, indexes need no adjusting
+pub fn load_index_bars(
+    date_dir: &Path,
+    ts_code: &str,
+    start_date: &str,
+    end_date: &str,
+) -> Result<Vec<StockDaily>, Box<dyn Error>> {
+    Ok(read_index_daily(date_dir, ts_code)?
+        .into_iter()
+        .filter(|b| b.trade_date.as_str() >= start_date && b.trade_date.as_str() <= end_date)
+        .collect())
+}
+
+/// implemented dividends of one stock, sorted by ex_date
+pub fn read_dividends(
+    date_dir: &Path,
+    ts_code: &str,
+) -> Result<Vec<StockDividend>, Box<dyn Error>> {
+    read_as(date_dir, "dividend", ts_code)
+}
+
+/// daily indicators of one stock, sorted by trade_date
+pub fn read_daily_basic(
+    date_dir: &Path,
+    ts_code: &str,
+) -> Result<Vec<StockDailyBasic>, Box<dyn Error>> {
+    read_as(date_dir, "daily_basic", ts_code)
+}
+
+/// money flow of one stock, sorted by trade_date
+pub fn read_moneyflow(
+    date_dir: &Path,
+    ts_code: &str,
+) -> Result<Vec<StockMoneyflow>, Box<dyn Error>> {
+    read_as(date_dir, "moneyflow", ts_code)
+}
+
+/// one daily bar and its money flow of the same day
+pub type MoneyflowBar = (StockDaily, Option<StockMoneyflow>);
+
+/// every bar with the money flow of the same (ts_code, trade_date), None if there is none
+pub fn join_moneyflow(
+    bars: Vec<StockDaily>,
+    moneyflow_vec: Vec<StockMoneyflow>,
+) -> Vec<MoneyflowBar> {
+    let mut moneyflow_map: HashMap<(String, String), StockMoneyflow> = moneyflow_vec
+        .into_iter()
+        .map(|m| ((m.ts_code.clone(), m.trade_date.clone()), m))
+        .collect();
+    bars.into_iter()
+        .map(|bar| {
+            let moneyflow = moneyflow_map.remove(&(bar.ts_code.clone(), bar.trade_date.clone()));
+            (bar, moneyflow)
+        })
+        .collect()
+}
+
+/// bars of one stock from start_date to end_date joined with its money flow
+pub fn load_bars_with_moneyflow(
+    date_dir: &Path,
+    ts_code: &str,
+    start_date: &str,
+    end_date: &str,
+    adjust: Adjust,
+) -> Result<Vec<MoneyflowBar>, Box<dyn Error>> {
+    let bars = load_bars(date_dir, ts_code, start_date, end_date, adjust)?;
+    Ok(join_moneyflow(bars, read_moneyflow(date_dir, ts_code)?))
+}
+
+/// daily bars of one fund, sorted by trade_date
+pub fn read_fund_daily(date_dir: &Path, ts_code: &str) -> Result<Vec<StockDaily>, Box<dyn Error>> {
+    read_as(date_dir, "fund_daily", ts_code)
+}
+
+/// funds downloaded in date dir
+pub fn read_funds_list(date_dir: &Path) -> Result<Vec<FundBasic>, Box<dyn Error>> {
+    read_list(&date_dir.join("funds_list"))
+}
+
+/// adj factors of one stock, sorted by trade_date
+pub fn read_adj_factor(
+    date_dir: &Path,
+    ts_code: &str,
+) -> Result<Vec<StockAdjFactor>, Box<dyn Error>> {
+    read_as(date_dir, "adj_factor", ts_code)
+}
+
+/// adj factors of one fund, sorted by trade_date
+pub fn read_fund_adj(
+    date_dir: &Path,
+    ts_code: &str,
+) -> Result<Vec<StockAdjFactor>, Box<dyn Error>> {
+    read_as(date_dir, "fund_adj", ts_code)
+}
+
+// bars in the range, adj factors are read only when adjusting
+fn load_adjusted_bars(
+    bars: Vec<StockDaily>,
+    adj_factors: impl FnOnce() -> Result<Vec<StockAdjFactor>, Box<dyn Error>>,
+    start_date: &str,
+    end_date: &str,
+    adjust: Adjust,
+) -> Result<Vec<StockDaily>, Box<dyn Error>> {
+    let bars: Vec<StockDaily> = bars
+        .into_iter()
+        .filter(|b| b.trade_date.as_str() >= start_date && b.trade_date.as_str() <= end_date)
+        .collect();
+    if adjust == Adjust::None {
+        return Ok(bars);
+    }
+    Ok(adjust_bars(&bars, &adj_factors()?, adjust)?)
+}
+
+/// bars of one stock from start_date to end_date, qfq is based on the latest day in data,
+/// not on end_date, so one day's qfq price doesn't change with the range
+pub fn load_bars(
+    date_dir: &Path,
+    ts_code: &str,
+    start_date: &str,
+    end_date: &str,
+    adjust: Adjust,
+) -> Result<Vec<StockDaily>, Box<dyn Error>> {
+    load_adjusted_bars(
+        read_daily(date_dir, ts_code)?,
+        || read_adj_factor(date_dir, ts_code),
+        start_date,
+        end_date,
+        adjust,
+    )
+}
+
+/// bars of one fund from start_date to end_date, adjusted like stocks
+pub fn load_fund_bars(
+    date_dir: &Path,
+    ts_code: &str,
+    start_date: &str,
+    end_date: &str,
+    adjust: Adjust,
+) -> Result<Vec<StockDaily>, Box<dyn Error>> {
+    load_adjusted_bars(
+        read_fund_daily(date_dir, ts_code)?,
+        || read_fund_adj(date_dir, ts_code),
+        start_date,
+        end_date,
+        adjust,
+    )
+}
+
+/// adjust bars by factors of the same stock, a day without factor uses the one before it
+pub fn adjust_bars(
+    bars: &[StockDaily],
+    adj_factors: &[StockAdjFactor],
+    adjust: Adjust,
+) -> Result<Vec<StockDaily>, String> {
+    if adjust == Adjust::None {
+        return Ok(bars.to_vec());
+    }
+    let mut adj_factors = adj_factors.to_vec();
+    adj_factors.sort_by(|a, b| a.trade_date.cmp(&b.trade_date));
+    let latest_adj_factor = match adj_factors.last() {
+        Some(latest) => latest.adj_factor,
+        None => return Err(String::from("no adj factor to adjust bars")),
+    };
+    if latest_adj_factor <= 0.0 {
+        return Err(format!("latest adj factor is error: {}", latest_adj_factor));
+    }
+
+    let mut adjusted_bars: Vec<StockDaily> = vec![];
+    for bar in bars {
+        // the first factor is used for days before it
+        let i = adj_factors.partition_point(|a| a.trade_date <= bar.trade_date);
+        let adj_factor = adj_factors[i.saturating_sub(1)].adj_factor;
+        let ratio = match adjust {
+            Adjust::Qfq => adj_factor / latest_adj_factor,
+            _ => adj_factor,
+        };
+        // pre_close is already ex-right on the ex-date, so it goes with the same day's factor
+        let mut adjusted_bar = bar.clone();
+        adjusted_bar.open = bar.open * ratio;
+        adjusted_bar.high = bar.high * ratio;
+        adjusted_bar.low = bar.low * ratio;
+        adjusted_bar.close = bar.close * ratio;
+        adjusted_bar.pre_close = bar.pre_close * ratio;
+        adjusted_bar.change = adjusted_bar.close - adjusted_bar.pre_close;
+        adjusted_bars.push(adjusted_bar);
+    }
+    Ok(adjusted_bars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn bar(trade_date: &str, close: f64, pre_close: f64) -> StockDaily {
+        StockDaily {
+            ts_code: "000001.SZ".to_owned(),
+            trade_date: trade_date.to_owned(),
+            open: close,
+            high: close,
+            low: close,
+            close,
+            pre_close,
+            change: close - pre_close,
+            pct_chg: (close - pre_close) / pre_close * 100.0,
+            vol: 100.0,
+            amount: 1000.0,
+        }
+    }
+
+    fn adj_factor(trade_date: &str, adj_factor: f64) -> StockAdjFactor {
+        StockAdjFactor {
+            ts_code: "000001.SZ".to_owned(),
+            trade_date: trade_date.to_owned(),
+            adj_factor,
+        }
+    }
+
+    #[test]
+    fn test_adjust_bars() {
+        // 10 送 10 on 20210105, raw close halves
+        let bars = vec![
+            bar("20210104", 20.0, 20.0),
+            bar("20210105", 10.0, 10.0),
+            bar("20210106", 11.0, 10.0),
+        ];
+        let adj_factors = vec![adj_factor("20210104", 1.0), adj_factor("20210105", 2.0)];
+
+        let qfq = adjust_bars(&bars, &adj_factors, Adjust::Qfq).unwrap();
+        assert_eq!(qfq[0].close, 10.0);
+        assert_eq!(qfq[1].close, 10.0);
+        assert_eq!(qfq[2].close, 11.0);
+        assert_eq!(qfq[2].change, 1.0);
+
+        let hfq = adjust_bars(&bars, &adj_factors, Adjust::Hfq).unwrap();
+        assert_eq!(hfq[0].close, 20.0);
+        assert_eq!(hfq[1].close, 20.0);
+        assert_eq!(hfq[2].close, 22.0);
+        assert_eq!(hfq[2].vol, 100.0);
+
+        assert!(adjust_bars(&bars, &[], Adjust::Qfq).is_err());
+        assert_eq!(
+            adjust_bars(&bars, &[], Adjust::None).unwrap()[0].close,
+            20.0
+        );
+    }
+
+    #[test]
+    fn test_load_bars() {
+        let date_dir = std::env::temp_dir().join("choose_some_test_load_bars");
+        if date_dir.exists() {
+            fs::remove_dir_all(&date_dir).unwrap();
+        }
+        fs::create_dir_all(date_dir.join("daily_data")).unwrap();
+        fs::create_dir_all(date_dir.join("adj_factor_data")).unwrap();
+        fs::write(
+            date_dir.join("daily_data").join("000001.SZ"),
+            "ts_code\ttrade_date\topen\thigh\tlow\tclose\tpre_close\tchange\tpct_chg\tvol\tamount\n\
+             000001.SZ\t20210104\t20\t20\t20\t20\t20\t0\t0\t100\t1000\n\
+             000001.SZ\t20210105\t10\t10\t10\t10\t10\t0\t0\t100\t1000\n",
+        )
+        .unwrap();
+        fs::write(
+            date_dir.join("adj_factor_data").join("000001.SZ"),
+            "ts_code\ttrade_date\tadj_factor\n\
+             000001.SZ\t20210104\t1\n\
+             000001.SZ\t20210105\t2\n",
+        )
+        .unwrap();
+
+        let raw = load_bars(&date_dir, "000001.SZ", "20210101", "20210131", Adjust::None).unwrap();
+        assert_eq!(raw.len(), 2);
+        assert_eq!(raw[0].close, 20.0);
+
+        // qfq base is the latest day in data, not the end of the range
+        let qfq = load_bars(&date_dir, "000001.SZ", "20210101", "20210104", Adjust::Qfq).unwrap();
+        assert_eq!(qfq.len(), 1);
+        assert_eq!(qfq[0].close, 10.0);
+
+        assert!(load_bars(&date_dir, "000002.SZ", "20210101", "20210131", Adjust::None).is_err());
+
+        fs::remove_dir_all(&date_dir).unwrap();
+    }
+
+    #[test]
+    fn test_join_moneyflow() {
+        let bars = vec![bar("20210104", 20.0, 20.0), bar("20210105", 10.0, 10.0)];
+        let moneyflow: StockMoneyflow = serde_json::from_value(serde_json::json!({
+            "ts_code": "000001.SZ",
+            "trade_date": "20210105",
+            "buy_lg_amount": 9.0,
+            "net_mf_amount": 18.0,
+        }))
+        .unwrap();
+        let joined = join_moneyflow(bars, vec![moneyflow.clone()]);
+        assert_eq!(joined.len(), 2);
+        assert_eq!(joined[0].1, None);
+        assert_eq!(joined[1].0.trade_date, "20210105");
+        assert_eq!(joined[1].1, Some(moneyflow));
+    }
+
+    #[test]
+    fn test_read_daily_basic() {
+        let date_dir = std::env::temp_dir().join("choose_some_test_read_daily_basic");
+        if date_dir.exists() {
+            fs::remove_dir_all(&date_dir).unwrap();
+        }
+        fs::create_dir_all(date_dir.join("daily_basic_data")).unwrap();
+        fs::write(
+            date_dir.join("daily_basic_data").join("000001.SZ"),
+            "ts_code\ttrade_date\tclose\tturnover_rate\tturnover_rate_f\tvolume_ratio\tpe\tpe_ttm\tpb\tps\tps_ttm\tdv_ratio\tdv_ttm\ttotal_share\tfloat_share\tfree_share\ttotal_mv\tcirc_mv\tlimit_status\n\
+             000001.SZ\t20210104\t19.34\t1.1\t1.5\t0.9\tnone\t15.2\t1.3\t2.1\t2.2\t0.9\t0.9\t1940592\t1940546\t1058349\t37531060\t37530169\tnone\n",
+        )
+        .unwrap();
+
+        let daily_basic = read_daily_basic(&date_dir, "000001.SZ").unwrap();
+        assert_eq!(daily_basic.len(), 1);
+        assert_eq!(daily_basic[0].close, 19.34);
+        assert_eq!(daily_basic[0].pe, None);
+        assert_eq!(daily_basic[0].pe_ttm, Some(15.2));
+        assert_eq!(daily_basic[0].limit_status, None);
+
+        fs::remove_dir_all(&date_dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_fund_bars() {
+        let date_dir = std::env::temp_dir().join("choose_some_test_load_fund_bars");
+        if date_dir.exists() {
+            fs::remove_dir_all(&date_dir).unwrap();
+        }
+        fs::create_dir_all(date_dir.join("fund_data")).unwrap();
+        fs::create_dir_all(date_dir.join("fund_adj_data")).unwrap();
+        fs::write(
+            date_dir.join("funds_list"),
+            "ts_code\tname\tmanagement\tfund_type\tinvest_type\tmarket\tstatus\tlist_date\tdelist_date\n\
+             510300.SH\t沪深300ETF\t华泰柏瑞基金\t股票型\t被动指数型\tE\tL\t20120528\tnone\n",
+        )
+        .unwrap();
+        fs::write(
+            date_dir.join("fund_data").join("510300.SH"),
+            "ts_code\ttrade_date\topen\thigh\tlow\tclose\tpre_close\tchange\tpct_chg\tvol\tamount\n\
+             510300.SH\t20210104\t5.2\t5.3\t5.2\t5.3\t5.2\t0.1\t1.9\t100\t1000\n",
+        )
+        .unwrap();
+        fs::write(
+            date_dir.join("fund_adj_data").join("510300.SH"),
+            "ts_code\ttrade_date\tadj_factor\n\
+             510300.SH\t20210104\t2\n",
+        )
+        .unwrap();
+
+        let funds_list = read_funds_list(&date_dir).unwrap();
+        assert_eq!(funds_list[0].delist_date, None);
+        let hfq =
+            load_fund_bars(&date_dir, "510300.SH", "20210101", "20210131", Adjust::Hfq).unwrap();
+        assert_eq!(hfq[0].close, 10.6);
+
+        fs::remove_dir_all(&date_dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_index_bars() {
+        let date_dir = std::env::temp_dir().join("choose_some_test_load_index_bars");
+        if date_dir.exists() {
+            fs::remove_dir_all(&date_dir).unwrap();
+        }
+        fs::create_dir_all(date_dir.join("index_data")).unwrap();
+        fs::write(
+            date_dir.join("index_data").join("index_list"),
+            "ts_code\tname\tmarket\tpublisher\tcategory\tbase_date\tbase_point\tlist_date\n\
+             000300.SH\t沪深300\tSSE\t中证公司\t规模指数\t20041231\t1000\t20050408\n",
+        )
+        .unwrap();
+        fs::write(
+            date_dir.join("index_data").join("000300.SH"),
+            "ts_code\ttrade_date\topen\thigh\tlow\tclose\tpre_close\tchange\tpct_chg\tvol\tamount\n\
+             000300.SH\t20210104\t5212\t5283\t5190\t5267\t5211\t56\t1.07\t2e8\t3e8\n\
+             000300.SH\t20210105\t5245\t5368\t5234\t5368\t5267\t101\t1.91\t2e8\t3e8\n",
+        )
+        .unwrap();
+
+        let index_list = read_index_list(&date_dir).unwrap();
+        assert_eq!(index_list[0].name, "沪深300");
+        assert_eq!(index_list[0].base_point, Some(1000.0));
+        let bars = load_index_bars(&date_dir, "000300.SH", "20210105", "20210131").unwrap();
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].close, 5368.0);
+
+        fs::remove_dir_all(&date_dir).unwrap();
+    }
+}
@@ -0,0 +1,720 @@
+/// Mock tushare pro server for integration tests
+/// speaks the json over POST of api.waditu.com: {api_name, token, params, fields} in,
+/// {request_id, code, msg, data: {fields, items, has_more}} out.
+/// trade_cal, stock_basic, daily and daily_basic are served from synthetic data,
+/// weekdays are open days and prices are made up, or from <fixture-dir>/<api_name>.json
+/// holding the data object of a real response: {"fields": [...], "items": [[...]]}.
+/// rows are filtered by ts_code, exchange, market, list_status, is_open, start_date and end_date,
+/// pages are cut by offset and limit.
+///
+/// --addr 127.0.0.1:0 listens on an ephemeral port, the first line printed has the url.
+///
+/// cargo run --bin mock_tushare -- --page-size 100 --error daily=40203@2 --slow daily_basic=3000
+/// TUSHARE_TOKEN=any DATA_DIR=/tmp/data cargo run -- --tushare-api-url http://127.0.0.1:7878 -t daily
+use chrono::{Datelike, Duration as DateDuration, NaiveDate, Weekday};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// mock tushare pro api server.
+#[derive(StructOpt)]
+struct Opt {
+    /// listen address
+    #[structopt(long = "addr", default_value = "127.0.0.1:7878")]
+    addr: String,
+
+    /// token the client must send, any token if not given
+    #[structopt(long = "token")]
+    token: Option<String>,
+
+    /// dir of <api_name>.json data served instead of synthetic data
+    #[structopt(long = "fixture-dir")]
+    fixture_dir: Option<PathBuf>,
+
+    /// synthetic stocks, half SSE and half SZSE 主板
+    #[structopt(long = "stocks", default_value = "10")]
+    stocks: usize,
+
+    /// max rows of one response, smaller than limit makes has_more pages
+    #[structopt(long = "page-size", default_value = "5000")]
+    page_size: usize,
+
+    /// answer an api with an error code: api=code[@times][:msg], * for every api,
+    /// like daily=40203@2 for the first 2 calls only, can be given many times
+    #[structopt(long = "error")]
+    errors: Vec<ErrorRule>,
+
+    /// answer an api after some milliseconds: api=ms, * for every api, can be given many times
+    #[structopt(long = "slow")]
+    slows: Vec<SlowRule>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct ErrorRule {
+    api_name: String,
+    code: i64,
+    // None is every call
+    times: Option<usize>,
+    msg: String,
+}
+
+impl FromStr for ErrorRule {
+    type Err = String;
+    fn from_str(a_str: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            format!(
+                "could not parse error {}, should be api=code[@times][:msg]",
+                a_str
+            )
+        };
+        let mut kv = a_str.splitn(2, '=');
+        let api_name = kv.next().unwrap_or_default().trim();
+        let rule = kv.next().ok_or_else(err)?;
+        let mut code_msg = rule.splitn(2, ':');
+        let code_times = code_msg.next().unwrap_or_default();
+        let mut code_times = code_times.splitn(2, '@');
+        let code: i64 = code_times
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .parse()
+            .map_err(|_| err())?;
+        let times = match code_times.next() {
+            Some(times) => Some(times.trim().parse::<usize>().map_err(|_| err())?),
+            None => None,
+        };
+        if api_name.is_empty() {
+            return Err(err());
+        }
+        Ok(ErrorRule {
+            api_name: api_name.to_owned(),
+            code,
+            times,
+            msg: match code_msg.next() {
+                Some(msg) => msg.to_owned(),
+                None => default_msg(code).to_owned(),
+            },
+        })
+    }
+}
+
+// messages like tushare's, the client tells errors by code and msg
+fn default_msg(code: i64) -> &'static str {
+    match code {
+        40101 => "您的token不对，请确认。",
+        40203 => "抱歉，您每分钟最多访问该接口500次",
+        -2001 => "参数错误",
+        -1 => "系统内部错误",
+        _ => "系统繁忙",
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct SlowRule {
+    api_name: String,
+    delay: Duration,
+}
+
+impl FromStr for SlowRule {
+    type Err = String;
+    fn from_str(a_str: &str) -> Result<Self, Self::Err> {
+        let mut kv = a_str.splitn(2, '=');
+        match (kv.next(), kv.next().map(|ms| ms.trim().parse::<u64>())) {
+            (Some(api_name), Some(Ok(ms))) if !api_name.trim().is_empty() => Ok(SlowRule {
+                api_name: api_name.trim().to_owned(),
+                delay: Duration::from_millis(ms),
+            }),
+            _ => Err(format!("could not parse slow {}, should be api=ms", a_str)),
+        }
+    }
+}
+
+// fields and items of one api
+type Data = (Vec<String>, Vec<Vec<Value>>);
+
+struct Server {
+    opt: Opt,
+    // calls of every error rule so far
+    error_calls: Mutex<HashMap<usize, usize>>,
+    requests: Mutex<usize>,
+}
+
+impl Server {
+    fn new(opt: Opt) -> Server {
+        Server {
+            opt,
+            error_calls: Mutex::new(HashMap::new()),
+            requests: Mutex::new(0),
+        }
+    }
+
+    fn delay(&self, api_name: &str) -> Option<Duration> {
+        self.opt
+            .slows
+            .iter()
+            .find(|s| s.api_name == api_name || s.api_name == "*")
+            .map(|s| s.delay)
+    }
+
+    // an error rule of the api that is not used up
+    fn injected_error(&self, api_name: &str) -> Option<(i64, String)> {
+        let mut error_calls = self.error_calls.lock().unwrap();
+        for (i, rule) in self.opt.errors.iter().enumerate() {
+            if rule.api_name != api_name && rule.api_name != "*" {
+                continue;
+            }
+            let calls = error_calls.entry(i).or_insert(0);
+            if rule.times.map_or(true, |times| *calls < times) {
+                *calls += 1;
+                return Some((rule.code, rule.msg.clone()));
+            }
+        }
+        None
+    }
+
+    /// response body of one request body
+    fn handle(&self, body: &str) -> String {
+        let request_id = {
+            let mut requests = self.requests.lock().unwrap();
+            *requests += 1;
+            format!("mock-{}", requests)
+        };
+        let error = |code: i64, msg: &str| {
+            json!({"request_id": request_id, "code": code, "msg": msg, "data": null}).to_string()
+        };
+
+        let request: Value = match serde_json::from_str(body) {
+            Ok(request) => request,
+            Err(e) => return error(-2001, &format!("请求格式错误: {}", e)),
+        };
+        let api_name = request["api_name"].as_str().unwrap_or_default();
+        if let Some(token) = &self.opt.token {
+            if request["token"].as_str() != Some(token) {
+                return error(40101, default_msg(40101));
+            }
+        }
+        if let Some((code, msg)) = self.injected_error(api_name) {
+            return error(code, &msg);
+        }
+        let params: HashMap<String, String> = match request["params"].as_object() {
+            Some(params) => params
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        k.clone(),
+                        v.as_str()
+                            .map(|s| s.to_owned())
+                            .unwrap_or_else(|| v.to_string()),
+                    )
+                })
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        let (fields, items) = match self.data(api_name, &params) {
+            Ok(data) => data,
+            Err((code, msg)) => return error(code, &msg),
+        };
+        let items = filter_items(&fields, items, &params);
+        let (fields, items) = select_fields(
+            fields,
+            items,
+            request["fields"].as_str().unwrap_or_default(),
+        );
+
+        let offset: usize = params
+            .get("offset")
+            .and_then(|o| o.parse().ok())
+            .unwrap_or(0);
+        let limit: usize = params
+            .get("limit")
+            .and_then(|l| l.parse().ok())
+            .unwrap_or(self.opt.page_size)
+            .min(self.opt.page_size);
+        let page: Vec<Vec<Value>> = items.iter().skip(offset).take(limit).cloned().collect();
+        let has_more = offset + page.len() < items.len();
+        json!({
+            "request_id": request_id,
+            "code": 0,
+            "msg": "",
+            "data": {"fields": fields, "items": page, "has_more": has_more},
+        })
+        .to_string()
+    }
+
+    // all rows of one api, fixture first
+    fn data(
+        &self,
+        api_name: &str,
+        params: &HashMap<String, String>,
+    ) -> Result<Data, (i64, String)> {
+        if let Some(fixture_dir) = &self.opt.fixture_dir {
+            let file_name = fixture_dir.join(format!("{}.json", api_name));
+            if file_name.exists() {
+                let data: Value = fs::read_to_string(&file_name)
+                    .ok()
+                    .and_then(|content| serde_json::from_str(&content).ok())
+                    .ok_or((-1, format!("fixture {:?} is not json", file_name)))?;
+                let fields = data["fields"]
+                    .as_array()
+                    .map(|f| {
+                        f.iter()
+                            .map(|f| f.as_str().unwrap_or_default().to_owned())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let items = data["items"]
+                    .as_array()
+                    .map(|items| items.iter().filter_map(|i| i.as_array().cloned()).collect())
+                    .unwrap_or_default();
+                return Ok((fields, items));
+            }
+        }
+        let start_date = date_param(params, "start_date", "20200101")?;
+        let end_date = date_param(params, "end_date", "20211231")?;
+        match api_name {
+            "trade_cal" => Ok(trade_cal(
+                params.get("exchange").map(|e| e.as_str()).unwrap_or("SSE"),
+                start_date,
+                end_date,
+            )),
+            "stock_basic" => Ok(stock_basic(self.opt.stocks)),
+            "daily" => Ok(daily(&self.ts_codes(params), start_date, end_date)),
+            "daily_basic" => Ok(daily_basic(&self.ts_codes(params), start_date, end_date)),
+            _ => Err((-2001, format!("接口名称错误: {}", api_name))),
+        }
+    }
+
+    fn ts_codes(&self, params: &HashMap<String, String>) -> Vec<String> {
+        match params.get("ts_code") {
+            Some(ts_codes) if !ts_codes.is_empty() => {
+                ts_codes.split(',').map(|c| c.trim().to_owned()).collect()
+            }
+            _ => (0..self.opt.stocks).map(ts_code).collect(),
+        }
+    }
+}
+
+fn date_param(
+    params: &HashMap<String, String>,
+    name: &str,
+    default: &str,
+) -> Result<NaiveDate, (i64, String)> {
+    let date = params.get(name).map(|d| d.as_str()).unwrap_or(default);
+    NaiveDate::parse_from_str(date, "%Y%m%d")
+        .map_err(|_| (-2001, format!("参数错误: {}={}", name, date)))
+}
+
+fn yyyymmdd(date: NaiveDate) -> String {
+    date.format("%Y%m%d").to_string()
+}
+
+fn is_open(date: NaiveDate) -> bool {
+    !matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+}
+
+fn prev_open(date: NaiveDate) -> NaiveDate {
+    let mut date = date - DateDuration::days(1);
+    while !is_open(date) {
+        date -= DateDuration::days(1);
+    }
+    date
+}
+
+fn open_days(start_date: NaiveDate, end_date: NaiveDate) -> Vec<NaiveDate> {
+    let mut days = vec![];
+    let mut date = start_date;
+    while date <= end_date {
+        if is_open(date) {
+            days.push(date);
+        }
+        date += DateDuration::days(1);
+    }
+    days
+}
+
+// half SSE and half SZSE
+fn ts_code(i: usize) -> String {
+    if i % 2 == 0 {
+        format!("{:06}.SH", 600000 + i / 2)
+    } else {
+        format!("{:06}.SZ", 1 + i / 2)
+    }
+}
+
+// the same stock and day always have the same price
+fn close(ts_code: &str, date: NaiveDate) -> f64 {
+    let seed: u32 = ts_code.bytes().map(|b| b as u32).sum();
+    let base = 5.0 + (seed % 50) as f64;
+    let day = date.num_days_from_ce() as f64;
+    let price = base * (1.0 + 0.2 * (day * 0.05 + seed as f64).sin());
+    (price * 100.0).round() / 100.0
+}
+
+fn round2(v: f64) -> f64 {
+    (v * 100.0).round() / 100.0
+}
+
+fn fields(names: &str) -> Vec<String> {
+    names.split(',').map(|f| f.trim().to_owned()).collect()
+}
+
+fn trade_cal(exchange: &str, start_date: NaiveDate, end_date: NaiveDate) -> Data {
+    let mut items = vec![];
+    let mut date = end_date;
+    // tushare returns the latest day first
+    while date >= start_date {
+        items.push(vec![
+            json!(exchange),
+            json!(yyyymmdd(date)),
+            json!(if is_open(date) { 1 } else { 0 }),
+            json!(yyyymmdd(prev_open(date))),
+        ]);
+        date -= DateDuration::days(1);
+    }
+    (fields("exchange,cal_date,is_open,pretrade_date"), items)
+}
+
+fn stock_basic(stocks: usize) -> Data {
+    let items = (0..stocks)
+        .map(|i| {
+            let ts_code = ts_code(i);
+            let exchange = if ts_code.ends_with(".SH") {
+                "SSE"
+            } else {
+                "SZSE"
+            };
+            vec![
+                json!(ts_code),
+                json!(&ts_code[..6]),
+                json!(format!("模拟{}", i)),
+                json!("上海"),
+                json!("银行"),
+                json!(format!("模拟股份{}有限公司", i)),
+                json!(format!("Mock {} Co., Ltd.", i)),
+                json!(format!("mn{}", i)),
+                json!("主板"),
+                json!(exchange),
+                json!("CNY"),
+                json!("L"),
+                json!("20100104"),
+                Value::Null,
+                json!("N"),
+            ]
+        })
+        .collect();
+    (
+        fields("ts_code,symbol,name,area,industry,fullname,enname,cnspell,market,exchange,curr_type,list_status,list_date,delist_date,is_hs"),
+        items,
+    )
+}
+
+fn daily(ts_codes: &[String], start_date: NaiveDate, end_date: NaiveDate) -> Data {
+    let mut items = vec![];
+    for date in open_days(start_date, end_date).into_iter().rev() {
+        for ts_code in ts_codes {
+            let close = close(ts_code, date);
+            let pre_close = self::close(ts_code, prev_open(date));
+            let open = round2((close + pre_close) / 2.0);
+            let change = round2(close - pre_close);
+            let vol = 10000.0 + (close * 1000.0).round();
+            items.push(vec![
+                json!(ts_code),
+                json!(yyyymmdd(date)),
+                json!(open),
+                json!(round2(open.max(close) * 1.01)),
+                json!(round2(open.min(close) * 0.99)),
+                json!(close),
+                json!(pre_close),
+                json!(change),
+                json!(round2(change / pre_close * 100.0)),
+                json!(vol),
+                json!(round2(vol * close / 10.0)),
+            ]);
+        }
+    }
+    (
+        fields("ts_code,trade_date,open,high,low,close,pre_close,change,pct_chg,vol,amount"),
+        items,
+    )
+}
+
+fn daily_basic(ts_codes: &[String], start_date: NaiveDate, end_date: NaiveDate) -> Data {
+    let mut items = vec![];
+    for date in open_days(start_date, end_date).into_iter().rev() {
+        for ts_code in ts_codes {
+            let close = close(ts_code, date);
+            let total_share = 100000.0;
+            items.push(vec![
+                json!(ts_code),
+                json!(yyyymmdd(date)),
+                json!(close),
+                json!(1.5),
+                json!(2.5),
+                json!(1.0),
+                json!(round2(close * 2.0)),
+                json!(round2(close * 1.8)),
+                json!(1.2),
+                json!(3.0),
+                json!(2.8),
+                Value::Null,
+                Value::Null,
+                json!(total_share),
+                json!(total_share * 0.8),
+                json!(total_share * 0.5),
+                json!(round2(total_share * close)),
+                json!(round2(total_share * 0.8 * close)),
+                Value::Null,
+            ]);
+        }
+    }
+    (
+        fields("ts_code,trade_date,close,turnover_rate,turnover_rate_f,volume_ratio,pe,pe_ttm,pb,ps,ps_ttm,dv_ratio,dv_ttm,total_share,float_share,free_share,total_mv,circ_mv,limit_status"),
+        items,
+    )
+}
+
+// rows matching the params of the columns they have
+fn filter_items(
+    fields: &[String],
+    items: Vec<Vec<Value>>,
+    params: &HashMap<String, String>,
+) -> Vec<Vec<Value>> {
+    let column = |name: &str| fields.iter().position(|f| f == name);
+    let text = |item: &[Value], i: usize| match &item[i] {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    };
+    let date_column = column("trade_date").or_else(|| column("cal_date"));
+    items
+        .into_iter()
+        .filter(|item| {
+            for name in &["exchange", "market", "list_status", "is_open"] {
+                if let (Some(value), Some(i)) = (params.get(*name), column(name)) {
+                    if !value.is_empty() && text(item, i) != *value {
+                        return false;
+                    }
+                }
+            }
+            if let (Some(ts_codes), Some(i)) = (params.get("ts_code"), column("ts_code")) {
+                if !ts_codes.is_empty() && !ts_codes.split(',').any(|c| c.trim() == text(item, i)) {
+                    return false;
+                }
+            }
+            if let Some(i) = date_column {
+                let date = text(item, i);
+                if params.get("start_date").is_some_and(|d| date < *d)
+                    || params.get("end_date").is_some_and(|d| date > *d)
+                {
+                    return false;
+                }
+            }
+            true
+        })
+        .collect()
+}
+
+// requested fields in the requested order, all fields if none, unknown ones are null
+fn select_fields(fields: Vec<String>, items: Vec<Vec<Value>>, requested: &str) -> Data {
+    let requested: Vec<String> = requested
+        .split(',')
+        .map(|f| f.trim().to_owned())
+        .filter(|f| !f.is_empty())
+        .collect();
+    if requested.is_empty() {
+        return (fields, items);
+    }
+    let columns: Vec<Option<usize>> = requested
+        .iter()
+        .map(|r| fields.iter().position(|f| f == r))
+        .collect();
+    let items = items
+        .into_iter()
+        .map(|item| {
+            columns
+                .iter()
+                .map(|c| c.and_then(|i| item.get(i).cloned()).unwrap_or(Value::Null))
+                .collect()
+        })
+        .collect();
+    (requested, items)
+}
+
+fn serve(server: &Server, mut stream: TcpStream) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let mut kv = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (kv.next(), kv.next()) {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8_lossy(&body);
+
+    let api_name = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|r| r["api_name"].as_str().map(|a| a.to_owned()))
+        .unwrap_or_default();
+    if let Some(delay) = server.delay(&api_name) {
+        thread::sleep(delay);
+    }
+    let response = server.handle(&body);
+    println!("{} {}", request_line.trim(), api_name);
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.len(),
+        response
+    )?;
+    stream.flush()
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let listener = TcpListener::bind(&opt.addr).unwrap_or_else(|e| {
+        eprintln!("bind {} error: {}", opt.addr, e);
+        std::process::exit(1);
+    });
+    // the bound address, port 0 listens on an ephemeral port
+    match listener.local_addr() {
+        Ok(addr) => println!("mock tushare listening on http://{}", addr),
+        Err(_) => println!("mock tushare listening on http://{}", opt.addr),
+    }
+    let server = Arc::new(Server::new(opt));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let server = server.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(&server, stream) {
+                        eprintln!("serve error: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("accept error: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(args: &[&str]) -> Server {
+        let mut all_args = vec!["mock_tushare"];
+        all_args.extend_from_slice(args);
+        Server::new(Opt::from_iter(all_args))
+    }
+
+    fn request(api_name: &str, params: Value, fields: &str) -> String {
+        json!({"api_name": api_name, "token": "t", "params": params, "fields": fields}).to_string()
+    }
+
+    #[test]
+    fn test_pages_and_fields() {
+        let server = server(&["--page-size", "3"]);
+        let params = json!({"exchange": "SSE", "start_date": "20210901", "end_date": "20210910", "offset": "0", "limit": "5000"});
+        let res: Value = serde_json::from_str(&server.handle(&request(
+            "trade_cal",
+            params,
+            "cal_date, is_open",
+        )))
+        .unwrap();
+        assert_eq!(res["code"], 0);
+        assert_eq!(res["data"]["fields"], json!(["cal_date", "is_open"]));
+        assert_eq!(res["data"]["items"][0], json!(["20210910", 1]));
+        assert_eq!(res["data"]["has_more"], true);
+
+        let params = json!({"exchange": "SSE", "start_date": "20210901", "end_date": "20210910", "offset": "9", "limit": "5000"});
+        let res: Value =
+            serde_json::from_str(&server.handle(&request("trade_cal", params, ""))).unwrap();
+        assert_eq!(res["data"]["items"].as_array().unwrap().len(), 1);
+        assert_eq!(res["data"]["items"][0][3], "20210831");
+        assert_eq!(res["data"]["has_more"], false);
+    }
+
+    #[test]
+    fn test_synthetic_data() {
+        let server = server(&["--stocks", "4"]);
+        let res: Value = serde_json::from_str(&server.handle(&request(
+            "stock_basic",
+            json!({"exchange": "SZSE", "list_status": "L"}),
+            "ts_code,exchange",
+        )))
+        .unwrap();
+        assert_eq!(
+            res["data"]["items"],
+            json!([["000001.SZ", "SZSE"], ["000002.SZ", "SZSE"]])
+        );
+
+        let params = json!({"ts_code": "600000.SH,000001.SZ", "start_date": "20210903", "end_date": "20210906"});
+        let res: Value =
+            serde_json::from_str(&server.handle(&request("daily", params.clone(), ""))).unwrap();
+        let items = res["data"]["items"].as_array().unwrap();
+        // friday and monday of two stocks, pre_close is the close of the day before
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0][1], "20210906");
+        assert_eq!(items[0][6], items[2][5]);
+
+        let res: Value =
+            serde_json::from_str(&server.handle(&request("daily_basic", params, ""))).unwrap();
+        assert_eq!(res["data"]["items"][0][11], Value::Null);
+
+        let res: Value =
+            serde_json::from_str(&server.handle(&request("income", json!({}), ""))).unwrap();
+        assert_eq!(res["code"], -2001);
+    }
+
+    #[test]
+    fn test_injected_errors() {
+        let server = server(&[
+            "--error",
+            "daily=40203@2",
+            "--error",
+            "*=-1@1:系统内部错误",
+            "--slow",
+            "daily=10",
+            "--token",
+            "t",
+        ]);
+        assert_eq!(server.delay("daily"), Some(Duration::from_millis(10)));
+        assert_eq!(server.delay("trade_cal"), None);
+
+        let code = |api_name: &str| {
+            let res: Value =
+                serde_json::from_str(&server.handle(&request(api_name, json!({}), ""))).unwrap();
+            res["code"].as_i64().unwrap()
+        };
+        assert_eq!(code("daily"), 40203);
+        assert_eq!(code("daily"), 40203);
+        assert_eq!(code("daily"), -1);
+        assert_eq!(code("daily"), 0);
+        assert_eq!(code("trade_cal"), 0);
+
+        let body = json!({"api_name": "daily", "token": "wrong", "params": {}, "fields": ""});
+        let res: Value = serde_json::from_str(&server.handle(&body.to_string())).unwrap();
+        assert_eq!(res["code"], 40101);
+
+        assert!("daily".parse::<ErrorRule>().is_err());
+        assert!("daily=x".parse::<ErrorRule>().is_err());
+        assert!("daily=1000".parse::<SlowRule>().is_ok());
+    }
+}
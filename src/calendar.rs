@@ -0,0 +1,237 @@
+/// Trading calendar of one exchange
+/// SSE and SZSE come from trade_cal, HKEX from hk_tradecal.
+/// every exchange is stored in trade_cal/<exchange> of a date dir, one row a calendar day,
+/// open and closed days: exchange, cal_date, is_open, pretrade_date.
+/// dates are yyyymmdd strings, so they compare as dates.
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const EXCHANGES: [&str; 3] = ["SSE", "SZSE", "HKEX"];
+pub const CALENDAR_HEADER: &str = "exchange\tcal_date\tis_open\tpretrade_date";
+
+/// calendar file of one exchange in date dir
+pub fn calendar_file(date_dir: &Path, exchange: &str) -> PathBuf {
+    date_dir.join("trade_cal").join(exchange)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CalendarDay {
+    pub exchange: String,
+    pub cal_date: String,
+    pub is_open: bool,
+    // the open day before cal_date
+    pub pretrade_date: Option<String>,
+}
+
+impl CalendarDay {
+    pub fn from_string(a_str: &str) -> Result<CalendarDay, String> {
+        let v: Vec<&str> = a_str.split('\t').collect();
+        if v.len() != 4 {
+            return Err(format!("calendar day should have 4 fields: {}", a_str));
+        }
+        let is_open = match v[2] {
+            "1" => true,
+            "0" => false,
+            _ => return Err(format!("calendar day is_open should be 0 or 1: {}", a_str)),
+        };
+        Ok(CalendarDay {
+            exchange: v[0].to_owned(),
+            cal_date: v[1].to_owned(),
+            is_open,
+            pretrade_date: match v[3] {
+                "none" | "" => None,
+                pretrade_date => Some(pretrade_date.to_owned()),
+            },
+        })
+    }
+}
+
+impl fmt::Display for CalendarDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.exchange,
+            self.cal_date,
+            if self.is_open { 1 } else { 0 },
+            self.pretrade_date.as_deref().unwrap_or("none")
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TradingCalendar {
+    exchange: String,
+    // sorted by cal_date, one a date
+    days: Vec<CalendarDay>,
+}
+
+impl TradingCalendar {
+    pub fn new(exchange: &str, mut days: Vec<CalendarDay>) -> TradingCalendar {
+        days.retain(|d| d.exchange == exchange);
+        days.sort_by(|a, b| a.cal_date.cmp(&b.cal_date));
+        days.dedup_by(|a, b| a.cal_date == b.cal_date);
+        TradingCalendar {
+            exchange: exchange.to_owned(),
+            days,
+        }
+    }
+
+    /// calendar of one exchange stored in date dir
+    pub fn load(date_dir: &Path, exchange: &str) -> Result<TradingCalendar, Box<dyn Error>> {
+        let file_name = calendar_file(date_dir, exchange);
+        let content = fs::read_to_string(&file_name)
+            .map_err(|e| format!("read {:?} error: {}", file_name, e))?;
+        let mut days: Vec<CalendarDay> = vec![];
+        for line in content.lines().skip(1).filter(|l| !l.is_empty()) {
+            days.push(CalendarDay::from_string(line)?);
+        }
+        Ok(TradingCalendar::new(exchange, days))
+    }
+
+    pub fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    pub fn days(&self) -> &[CalendarDay] {
+        &self.days
+    }
+
+    /// the first and the last calendar day, None if no day
+    pub fn date_range(&self) -> Option<(&str, &str)> {
+        match (self.days.first(), self.days.last()) {
+            (Some(first), Some(last)) => Some((&first.cal_date, &last.cal_date)),
+            _ => None,
+        }
+    }
+
+    fn day(&self, date: &str) -> Option<&CalendarDay> {
+        self.days
+            .binary_search_by(|d| d.cal_date.as_str().cmp(date))
+            .ok()
+            .map(|i| &self.days[i])
+    }
+
+    /// a date out of the calendar is not open
+    pub fn is_open(&self, date: &str) -> bool {
+        self.day(date).map(|d| d.is_open).unwrap_or(false)
+    }
+
+    /// the first open day after date
+    pub fn next_open(&self, date: &str) -> Option<&str> {
+        let start = self.days.partition_point(|d| d.cal_date.as_str() <= date);
+        self.days[start..]
+            .iter()
+            .find(|d| d.is_open)
+            .map(|d| d.cal_date.as_str())
+    }
+
+    /// the last open day before date, pretrade_date of the first calendar day
+    /// tells the one before the calendar
+    pub fn prev_open(&self, date: &str) -> Option<&str> {
+        let end = self.days.partition_point(|d| d.cal_date.as_str() < date);
+        match self.days[..end].iter().rev().find(|d| d.is_open) {
+            Some(d) => Some(d.cal_date.as_str()),
+            None => self
+                .days
+                .get(end)
+                .filter(|d| d.cal_date == date)
+                .and_then(|d| d.pretrade_date.as_deref()),
+        }
+    }
+
+    /// open days from start_date to end_date, both included
+    pub fn open_days<'a>(
+        &'a self,
+        start_date: &str,
+        end_date: &str,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        let start = self
+            .days
+            .partition_point(|d| d.cal_date.as_str() < start_date);
+        let end = self
+            .days
+            .partition_point(|d| d.cal_date.as_str() <= end_date)
+            .max(start);
+        self.days[start..end]
+            .iter()
+            .filter(|d| d.is_open)
+            .map(|d| d.cal_date.as_str())
+    }
+
+    /// number of open days from start_date to end_date, both included
+    pub fn trading_days_between(&self, start_date: &str, end_date: &str) -> usize {
+        self.open_days(start_date, end_date).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calendar() -> TradingCalendar {
+        // 20210101 is a holiday, 20210102 and 20210103 the weekend
+        let days = vec![
+            "SSE\t20210105\t1\t20210104",
+            "SSE\t20210101\t0\t20201231",
+            "SSE\t20210102\t0\t20201231",
+            "SSE\t20210103\t0\t20201231",
+            "SSE\t20210104\t1\t20201231",
+            "SZSE\t20210104\t1\t20201231",
+            "SSE\t20210106\t1\t20210105",
+            "SSE\t20210106\t1\t20210105",
+        ]
+        .into_iter()
+        .map(|d| CalendarDay::from_string(d).unwrap())
+        .collect();
+        TradingCalendar::new("SSE", days)
+    }
+
+    #[test]
+    fn test_calendar_navigation() {
+        let calendar = calendar();
+        assert_eq!(calendar.days().len(), 6);
+        assert_eq!(calendar.date_range(), Some(("20210101", "20210106")));
+
+        assert!(!calendar.is_open("20210101"));
+        assert!(calendar.is_open("20210104"));
+        assert!(!calendar.is_open("20210107"));
+
+        assert_eq!(calendar.next_open("20210101"), Some("20210104"));
+        assert_eq!(calendar.next_open("20210104"), Some("20210105"));
+        assert_eq!(calendar.next_open("20210106"), None);
+
+        assert_eq!(calendar.prev_open("20210105"), Some("20210104"));
+        assert_eq!(calendar.prev_open("20210103"), Some("20201231"));
+        assert_eq!(calendar.prev_open("20210101"), Some("20201231"));
+        assert_eq!(calendar.prev_open("20201201"), None);
+
+        let open_days: Vec<&str> = calendar.open_days("20210102", "20210105").collect();
+        assert_eq!(open_days, vec!["20210104", "20210105"]);
+        assert_eq!(calendar.trading_days_between("20210101", "20210131"), 3);
+        assert_eq!(calendar.trading_days_between("20210106", "20210101"), 0);
+    }
+
+    #[test]
+    fn test_load_calendar() {
+        let date_dir = std::env::temp_dir().join("choose_some_test_load_calendar");
+        if date_dir.exists() {
+            fs::remove_dir_all(&date_dir).unwrap();
+        }
+        fs::create_dir_all(date_dir.join("trade_cal")).unwrap();
+        let calendar = calendar();
+        let rows: Vec<String> = calendar.days().iter().map(|d| d.to_string()).collect();
+        fs::write(
+            calendar_file(&date_dir, "SSE"),
+            format!("{}\n{}\n", CALENDAR_HEADER, rows.join("\n")),
+        )
+        .unwrap();
+
+        assert_eq!(TradingCalendar::load(&date_dir, "SSE").unwrap(), calendar);
+        assert!(TradingCalendar::load(&date_dir, "HKEX").is_err());
+
+        fs::remove_dir_all(&date_dir).unwrap();
+    }
+}
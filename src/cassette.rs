@@ -0,0 +1,188 @@
+/// Recorded tushare calls, so crawl runs without network
+/// record mode sends every call to tushare and saves the request and the response body
+/// to a json cassette file, the token is never saved.
+/// replay mode answers every call from the cassette and sends nothing,
+/// a call not in the cassette is an error.
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+pub const REDACTED_TOKEN: &str = "<redacted>";
+
+/// --cassette option
+#[derive(Debug, PartialEq, Clone)]
+pub enum CassetteMode {
+    Record(String),
+    Replay(String),
+}
+impl FromStr for CassetteMode {
+    type Err = crate::ParseError;
+    fn from_str(cassette: &str) -> Result<Self, Self::Err> {
+        let mut kv = cassette.trim().splitn(2, ':');
+        match (kv.next(), kv.next()) {
+            (Some("record"), Some(file_name)) if !file_name.is_empty() => {
+                Ok(CassetteMode::Record(file_name.to_owned()))
+            }
+            (Some("replay"), Some(file_name)) if !file_name.is_empty() => {
+                Ok(CassetteMode::Replay(file_name.to_owned()))
+            }
+            _ => Err("Could not parse cassette, should be record:<file> or replay:<file>"),
+        }
+    }
+}
+
+/// one call, params are sorted so the cassette file is stable
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub api_name: String,
+    pub params: BTreeMap<String, String>,
+    pub fields: String,
+    pub response: String,
+}
+
+impl Interaction {
+    fn is_call(&self, api_name: &str, params: &HashMap<String, String>, fields: &str) -> bool {
+        self.api_name == api_name
+            && self.fields == fields
+            && self.params.len() == params.len()
+            && params.iter().all(|(k, v)| self.params.get(k) == Some(v))
+    }
+}
+
+#[derive(Debug)]
+pub struct Cassette {
+    file_name: PathBuf,
+    replay: bool,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl Cassette {
+    /// replay reads the cassette file, record starts an empty one
+    pub fn open(mode: &CassetteMode) -> Result<Cassette, Box<dyn Error>> {
+        match mode {
+            CassetteMode::Record(file_name) => Ok(Cassette {
+                file_name: PathBuf::from(file_name),
+                replay: false,
+                interactions: Mutex::new(vec![]),
+            }),
+            CassetteMode::Replay(file_name) => {
+                let content = fs::read_to_string(file_name)
+                    .map_err(|e| format!("read cassette {} error: {}", file_name, e))?;
+                let interactions: Vec<Interaction> = serde_json::from_str(&content)
+                    .map_err(|e| format!("decode cassette {} error: {}", file_name, e))?;
+                Ok(Cassette {
+                    file_name: PathBuf::from(file_name),
+                    replay: true,
+                    interactions: Mutex::new(interactions),
+                })
+            }
+        }
+    }
+
+    pub fn is_replay(&self) -> bool {
+        self.replay
+    }
+
+    /// the recorded response body of one call
+    pub fn replay(
+        &self,
+        api_name: &str,
+        params: &HashMap<String, String>,
+        fields: &str,
+    ) -> Option<String> {
+        self.interactions
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|i| i.is_call(api_name, params, fields))
+            .map(|i| i.response.clone())
+    }
+
+    /// save one call, the same call recorded again replaces the old one.
+    /// the file is written every call, so an interrupted recording keeps what it got
+    pub fn record(
+        &self,
+        token: &str,
+        api_name: &str,
+        params: &HashMap<String, String>,
+        fields: &str,
+        response: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let redact = |s: &str| {
+            if token.is_empty() {
+                s.to_owned()
+            } else {
+                s.replace(token, REDACTED_TOKEN)
+            }
+        };
+        let interaction = Interaction {
+            api_name: api_name.to_owned(),
+            params: params.iter().map(|(k, v)| (k.clone(), redact(v))).collect(),
+            fields: fields.to_owned(),
+            response: redact(response),
+        };
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.retain(|i| {
+            i.api_name != interaction.api_name
+                || i.params != interaction.params
+                || i.fields != interaction.fields
+        });
+        interactions.push(interaction);
+        fs::write(
+            &self.file_name,
+            serde_json::to_string_pretty(&*interactions)?,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_replay() {
+        let file_name = std::env::temp_dir().join("choose_some_test_cassette.json");
+        let file_name = file_name.to_string_lossy().to_string();
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert("exchange".to_owned(), "SSE".to_owned());
+        params.insert("offset".to_owned(), "0".to_owned());
+        let body = r#"{"request_id":"1","code":0,"msg":"","data":{"fields":["cal_date"],"items":[["20210901"]],"has_more":false}}"#;
+
+        let recorder = Cassette::open(&format!("record:{}", file_name).parse().unwrap()).unwrap();
+        assert!(!recorder.is_replay());
+        recorder
+            .record("secret", "trade_cal", &params, "cal_date", "old")
+            .unwrap();
+        recorder
+            .record("secret", "trade_cal", &params, "cal_date", body)
+            .unwrap();
+        recorder
+            .record("secret", "daily", &params, "", "token secret is wrong")
+            .unwrap();
+        let content = fs::read_to_string(&file_name).unwrap();
+        assert!(!content.contains("secret"));
+
+        let player = Cassette::open(&CassetteMode::Replay(file_name.clone())).unwrap();
+        assert!(player.is_replay());
+        assert_eq!(
+            player.replay("trade_cal", &params, "cal_date"),
+            Some(body.to_owned())
+        );
+        assert_eq!(
+            player.replay("daily", &params, ""),
+            Some(format!("token {} is wrong", REDACTED_TOKEN))
+        );
+        assert_eq!(player.replay("trade_cal", &params, ""), None);
+        params.insert("offset".to_owned(), "5000".to_owned());
+        assert_eq!(player.replay("trade_cal", &params, "cal_date"), None);
+
+        assert!("play:a.json".parse::<CassetteMode>().is_err());
+        assert!("record:".parse::<CassetteMode>().is_err());
+        fs::remove_file(&file_name).unwrap();
+    }
+}
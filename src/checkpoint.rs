@@ -0,0 +1,196 @@
+/// Checkpoint of one download in date dir
+/// _CHECKPOINT file, first line is "start_date\tend_date\tappend" of the download,
+/// then one line for every finished group: "download type\tts_code,ts_code,..."
+/// a rerun skips finished groups, it is removed when _SUCCESS is written.
+use log::{info, warn};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, Once};
+use std::thread;
+
+pub const CHECKPOINT_FILE: &str = "_CHECKPOINT";
+
+static STOP: AtomicBool = AtomicBool::new(false);
+static INSTALL_CTRL_C: Once = Once::new();
+// held while a data file is written
+static WRITING: Mutex<()> = Mutex::new(());
+
+/// first ctrl-c asks the download to stop after the current group,
+/// second one exits once the data file being written is done
+pub fn install_ctrl_c_handler() {
+    INSTALL_CTRL_C.call_once(|| {
+        thread::spawn(|| {
+            let rt = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(rt) => rt,
+                Err(e) => {
+                    warn!("can not listen ctrl-c: {}", e);
+                    return;
+                }
+            };
+            rt.block_on(async {
+                if tokio::signal::ctrl_c().await.is_err() {
+                    return;
+                }
+                warn!("ctrl-c, stop after current group, ctrl-c again to exit now");
+                STOP.store(true, Ordering::SeqCst);
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    let _writing = write_guard();
+                    process::exit(130);
+                }
+            });
+        });
+    });
+}
+
+pub fn stop_requested() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
+/// hold it while writing a data file, the exit of a second ctrl-c waits for it
+pub fn write_guard() -> MutexGuard<'static, ()> {
+    WRITING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CheckpointHeader {
+    pub start_date: String,
+    pub end_date: String,
+    pub append: bool,
+}
+
+impl CheckpointHeader {
+    fn to_line(&self) -> String {
+        format!("{}\t{}\t{}", self.start_date, self.end_date, self.append)
+    }
+
+    fn from_line(a_string: &str) -> Option<CheckpointHeader> {
+        let a_vec: Vec<&str> = a_string.split('\t').collect();
+        if a_vec.len() != 3 {
+            return None;
+        }
+        Some(CheckpointHeader {
+            start_date: a_vec[0].to_owned(),
+            end_date: a_vec[1].to_owned(),
+            append: a_vec[2] == "true",
+        })
+    }
+}
+
+pub struct Checkpoint {
+    file_name: PathBuf,
+    // (download type, ts_code)
+    finished: HashSet<(String, String)>,
+}
+
+impl Checkpoint {
+    /// header of the unfinished download in date dir, None if no checkpoint
+    pub fn read_header(date_dir: &Path) -> Option<CheckpointHeader> {
+        let content = fs::read_to_string(date_dir.join(CHECKPOINT_FILE)).ok()?;
+        CheckpointHeader::from_line(content.lines().next()?)
+    }
+
+    /// load the checkpoint with the same header, or start a new one
+    pub fn open(date_dir: &Path, header: CheckpointHeader) -> Result<Checkpoint, Box<dyn Error>> {
+        let file_name = date_dir.join(CHECKPOINT_FILE);
+        let mut finished: HashSet<(String, String)> = HashSet::new();
+        if Checkpoint::read_header(date_dir).as_ref() == Some(&header) {
+            let content = fs::read_to_string(&file_name)?;
+            for line in content.lines().skip(1) {
+                let mut kv = line.splitn(2, '\t');
+                let download_type = kv.next().unwrap_or_default();
+                for ts_code in kv.next().unwrap_or_default().split(',') {
+                    finished.insert((download_type.to_owned(), ts_code.to_owned()));
+                }
+            }
+            info!("resume from checkpoint, {} finished", finished.len());
+        } else {
+            let mut file = fs::File::create(&file_name)?;
+            writeln!(&mut file, "{}", header.to_line())?;
+            file.sync_all()?;
+        }
+
+        Ok(Checkpoint {
+            file_name,
+            finished,
+        })
+    }
+
+    pub fn is_finished(&self, download_type: &str, ts_codes: &[String]) -> bool {
+        ts_codes.iter().all(|c| {
+            self.finished
+                .contains(&(download_type.to_owned(), c.to_owned()))
+        })
+    }
+
+    /// record one group finished, call it after the group's files are written
+    pub fn finish(
+        &mut self,
+        download_type: &str,
+        ts_codes: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut file = fs::OpenOptions::new().append(true).open(&self.file_name)?;
+        writeln!(&mut file, "{}\t{}", download_type, ts_codes.join(","))?;
+        file.sync_all()?;
+        for ts_code in ts_codes {
+            self.finished
+                .insert((download_type.to_owned(), ts_code.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// all groups are done
+    pub fn remove(self) -> Result<(), Box<dyn Error>> {
+        fs::remove_file(&self.file_name)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let date_dir = std::env::temp_dir().join("choose_some_test_checkpoint_resume");
+        if date_dir.exists() {
+            fs::remove_dir_all(&date_dir).unwrap();
+        }
+        fs::create_dir_all(&date_dir).unwrap();
+        let header = CheckpointHeader {
+            start_date: "20210104".to_owned(),
+            end_date: "20210917".to_owned(),
+            append: false,
+        };
+        let group = vec!["000001.SZ".to_owned(), "000002.SZ".to_owned()];
+
+        let mut checkpoint = Checkpoint::open(&date_dir, header.clone()).unwrap();
+        assert!(!checkpoint.is_finished("daily", &group));
+        checkpoint.finish("daily", &group).unwrap();
+
+        assert_eq!(Checkpoint::read_header(&date_dir), Some(header.clone()));
+        let checkpoint = Checkpoint::open(&date_dir, header.clone()).unwrap();
+        assert!(checkpoint.is_finished("daily", &group));
+        assert!(checkpoint.is_finished("daily", &group[1..]));
+        assert!(!checkpoint.is_finished("daily_basic", &group));
+
+        // another download starts over
+        let other_header = CheckpointHeader {
+            end_date: "20210924".to_owned(),
+            ..header
+        };
+        let checkpoint = Checkpoint::open(&date_dir, other_header).unwrap();
+        assert!(!checkpoint.is_finished("daily", &group));
+        checkpoint.remove().unwrap();
+        assert_eq!(Checkpoint::read_header(&date_dir), None);
+
+        fs::remove_dir_all(&date_dir).unwrap();
+    }
+}
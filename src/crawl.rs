@@ -1,28 +1,65 @@
 /// Data dir
 /// --2021-09-01 , dir means lastest hist data date
 /// ----daily_data , dir means hist data from start_date to data_date
+/// ----adj_factor_data , dir means adj factors of daily_data, one file a stock
+/// ----index_data , dir means daily of index codes, one file an index, and index_list of them
+/// ----fund_data , dir means daily of exchange listed funds (ETF, LOF), one file a fund
+/// ----fund_adj_data , dir means adj factors of fund_data, one file a fund
+/// ----funds_list , file means funds of fund_data
+/// ----income_data, balancesheet_data, cashflow_data, fina_indicator_data , dirs mean
+///     financial reports by ann_date, one file a stock, see financial.rs
+/// ----dividend_data , dir means implemented dividends by ex_date, one file a stock
+/// ----suspend_data , dir means suspended and resumed days, one file a stock
+/// ----stk_limit_data , dir means up and down limit prices, one file a stock
+/// ----moneyflow_data , dir means money flow by order size, one file a stock
+/// ----trade_cal , dir means open and closed days of SSE, SZSE and HKEX, one file an exchange
 /// ----stocks_list , file means stocks list on current day
+/// ----universe , file means exchange:market pairs of stocks_list
+/// ----validation.json , file means issues found checking daily_data, see validate.rs
 /// ----_SUCCESS , file means one download finish
+/// ----_CHECKPOINT , file means finished groups of one unfinished download
+/// every <dataset>_data dir is declared in the dataset registry, see dataset.rs.
+/// stocks list, calendars, daily and daily_basic come from the data source, see source.rs,
+/// other datasets are only on tushare.
+/// update mode renames the latest finished date dir to the new lastest trade date
+/// and appends the missing trade days to its files.
 use crate::Config;
-use crate::DownloadType;
+use crate::RunMode;
 use log::{debug, info, warn};
 use std::any::type_name;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
 use std::fmt;
 use std::fs;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
 use std::path::PathBuf;
-
-use crate::models::{StockBasic, StockDaily, StockDailyBasic, TushareRESTfulAPI};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use chrono::{Datelike, NaiveDate};
+
+use crate::calendar::{self, calendar_file, TradingCalendar, CALENDAR_HEADER};
+use crate::checkpoint::{self, Checkpoint, CheckpointHeader};
+use crate::dataset::{Calls, Codes, Dataset, Partition, DATASETS};
+use crate::models::{FundBasic, IndexBasic, StockBasic};
+use crate::plan::{DateWindow, DownloadPlan, MAX_ROWS_PER_CALL};
+use crate::source::{CsvSource, DataSource, DatasetRows, Source};
+use crate::tushare::TushareClient;
+use crate::universe::Universe;
+use crate::validate;
 
 fn _test_type<T>(_: T) {
     println!("{:?}", { type_name::<T>() });
 }
 
+// a report can be more than one year old when a backtest starts, annual reports come out by april
+const FINANCIAL_LOOKBACK_YEARS: i32 = 2;
+
 #[derive(Debug)]
 struct MyError(String);
 impl fmt::Display for MyError {
@@ -35,18 +72,181 @@ impl Error for MyError {}
 pub fn run(config: &Config) -> Result<(String, String), Box<dyn Error>> {
     info!("{} {}", config.data_start_date, config.data_end_date);
     let data_dir = Path::new(&config.data_dir);
-    let (earliest_trade_date, latest_trade_date) = crawl_trade_cal(config).unwrap();
-    let date_dir = data_dir.join(&latest_trade_date);
+    // tushare is kept to log how much the rate limit throttled
+    let mut tushare: Option<Arc<TushareClient>> = None;
+    let source: Arc<dyn DataSource> = match &config.source {
+        Source::Tushare => {
+            let client = Arc::new(TushareClient::from_config(config)?);
+            info!("tushare api url: {}", client.base_url());
+            tushare = Some(client.clone());
+            client
+        }
+        Source::Csv(dir) => Arc::new(CsvSource::new(Path::new(dir))?),
+    };
+    info!("data source: {}", source.name());
+
+    let (calendar_start_date, trade_dates, date_dir, update_from_dir) = match config.run_mode {
+        RunMode::Full => {
+            let (_, latest_trade_date, trade_dates) = crawl_trade_cal(
+                source.as_ref(),
+                &config.data_start_date,
+                &config.data_end_date,
+            )?;
+            (
+                config.data_start_date.clone(),
+                trade_dates,
+                data_dir.join(&latest_trade_date),
+                None,
+            )
+        }
+        RunMode::Update => {
+            match prepare_update(source.as_ref(), data_dir, &config.data_end_date)? {
+                UpdatePlan::UpToDate(stored_trade_date) => {
+                    info!("data is up to date: {}", stored_trade_date);
+                    return Ok((config.data_start_date.clone(), stored_trade_date));
+                }
+                UpdatePlan::Missing(
+                    calendar_start_date,
+                    trade_dates,
+                    date_dir,
+                    update_from_dir,
+                ) => (calendar_start_date, trade_dates, date_dir, update_from_dir),
+            }
+        }
+    };
+    let start_trade_date = trade_dates.iter().min().unwrap().to_owned();
+    let latest_trade_date = trade_dates.iter().max().unwrap().to_owned();
+    let append = config.run_mode == RunMode::Update;
+
+    // get stocks list of every market and list status, a stock is kept once,
+    // delisted ones are kept only if they traded in the range, their daily data stop at delisting
+    info!("universe: {}", config.universe);
+    let mut stocks_basic: Vec<StockBasic> = vec![];
+    let mut seen_ts_codes: HashSet<String> = HashSet::new();
+    for market in &config.universe.markets {
+        for list_status in &config.list_statuses {
+            for stock_basic in source.stocks_basic(market, *list_status)? {
+                if listed_in_range(
+                    &stock_basic.list_date,
+                    stock_basic.delist_date.as_deref(),
+                    &start_trade_date,
+                    &latest_trade_date,
+                ) && seen_ts_codes.insert(stock_basic.ts_code.clone())
+                {
+                    stocks_basic.push(stock_basic);
+                }
+            }
+        }
+    }
+
+    // index and fund lists are small, crawl them every time
+    // a source without tushare fails on a dataset it doesn't serve when the dataset is crawled
+    let datasets = config.download_type.datasets();
+    let downloads_codes = |codes: Codes| {
+        datasets
+            .iter()
+            .any(|d| d.partition == Partition::PerStock(Calls::OneCode(codes)))
+    };
 
-    // init dir
-    init_dir(&date_dir)?;
+    let mut index_basic_vec: Option<Vec<IndexBasic>> = None;
+    let mut index_codes: Vec<String> = vec![];
+    if downloads_codes(Codes::Indexes) {
+        index_basic_vec = Some(source.index_basic(&config.index_codes)?);
+        index_codes = config.index_codes.clone();
+    }
+    let mut fund_basic_vec: Option<Vec<FundBasic>> = None;
+    let mut fund_codes: Vec<String> = vec![];
+    if downloads_codes(Codes::Funds) {
+        let funds = crawl_funds(
+            source.as_ref(),
+            config,
+            &start_trade_date,
+            &latest_trade_date,
+        )?;
+        info!("{} funds", funds.len());
+        fund_codes = funds.iter().map(|f| f.ts_code.clone()).collect();
+        fund_basic_vec = Some(funds);
+    }
 
-    let token = config.tushare_token.to_owned();
-    // get stocks list
-    let sse_stocks_basic = crawl_stocks_basic(&token, "SSE", "主板")?;
-    let mut szse_stocks_basic = crawl_stocks_basic(&token, "SZSE", "主板")?;
-    let mut stocks_basic = sse_stocks_basic;
-    stocks_basic.append(&mut szse_stocks_basic);
+    if config.plan_only {
+        let ts_codes: Vec<String> = stocks_basic.iter().map(|s| s.ts_code.clone()).collect();
+        let plan = DownloadPlan::new(&ts_codes, &trade_dates, MAX_ROWS_PER_CALL);
+        println!("{}", plan);
+        let type_plans = type_plans(
+            &plan,
+            &datasets,
+            [&ts_codes, &index_codes, &fund_codes],
+            &trade_dates,
+            [&start_trade_date, &latest_trade_date],
+            append,
+        )?;
+        println!(
+            "calls of {} datasets: {}",
+            datasets.len(),
+            download_calls(&plan, &type_plans, &datasets)
+        );
+        return Ok((start_trade_date, latest_trade_date));
+    }
+
+    // calendars of every exchange from the first missing day, closed days included
+    let mut calendars: Vec<(&str, TradingCalendar)> = vec![];
+    for exchange in &calendar::EXCHANGES {
+        match source.trade_calendar(exchange, &calendar_start_date, &config.data_end_date) {
+            Ok(trading_calendar) => calendars.push((exchange, trading_calendar)),
+            // hk_tradecal needs more points than the A share apis
+            Err(e) if *exchange == "HKEX" => warn!("no {} calendar: {}", exchange, e),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // init dir only after the lists and calendars are crawled, keep the unfinished same download
+    // to resume. an update keeps the stored history, so it is never init
+    let header = CheckpointHeader {
+        start_date: start_trade_date.clone(),
+        end_date: latest_trade_date.clone(),
+        append,
+    };
+    match update_from_dir {
+        Some(update_from_dir) => start_update(&update_from_dir, &date_dir, &header)?,
+        None if append => init_sub_dirs(&date_dir)?,
+        None => {
+            if !date_dir.join("_SUCCESS").exists()
+                && Checkpoint::read_header(&date_dir).as_ref() == Some(&header)
+            {
+                info!("resume unfinished download in {:?}", date_dir);
+                init_sub_dirs(&date_dir)?;
+            } else {
+                init_dir(&date_dir)?;
+            }
+        }
+    }
+
+    // the appended days of stocks not in the stored universe have no history before them
+    if append {
+        match Universe::read(&date_dir) {
+            Some(stored_universe) if stored_universe != config.universe => warn!(
+                "universe {} is not the stored {}, new stocks only have data from {}",
+                config.universe, stored_universe, start_trade_date
+            ),
+            _ => {}
+        }
+    }
+    config.universe.write(&date_dir)?;
+
+    for (exchange, trading_calendar) in &calendars {
+        merge_data_file(
+            &calendar_file(&date_dir, exchange),
+            CALENDAR_HEADER,
+            trading_calendar
+                .days()
+                .iter()
+                .map(|d| d.to_string())
+                .collect(),
+            1,
+            &calendar_start_date,
+            &config.data_end_date,
+        )?;
+    }
 
     // wrtie stocks_list
     let stocks_list_file_name = date_dir.join("stocks_list");
@@ -54,79 +254,326 @@ pub fn run(config: &Config) -> Result<(String, String), Box<dyn Error>> {
 
     // read stocks_list
     let stocks_basic = read_stocks_list(&stocks_list_file_name).unwrap();
+    let ts_codes: Vec<String> = stocks_basic.iter().map(|s| s.ts_code.clone()).collect();
+    let plan = DownloadPlan::new(&ts_codes, &trade_dates, MAX_ROWS_PER_CALL);
+    info!("{}", plan);
+
+    if let Some(index_basic_vec) = &index_basic_vec {
+        write_index_list(
+            &date_dir.join("index_data").join("index_list"),
+            index_basic_vec,
+        )?;
+    }
+    if let Some(fund_basic_vec) = &fund_basic_vec {
+        write_funds_list(&date_dir.join("funds_list"), fund_basic_vec)?;
+    }
+
+    let type_plans = type_plans(
+        &plan,
+        &datasets,
+        [&ts_codes, &index_codes, &fund_codes],
+        &trade_dates,
+        [&start_trade_date, &latest_trade_date],
+        append,
+    )?;
+    info!(
+        "{} calls of {} datasets",
+        download_calls(&plan, &type_plans, &datasets),
+        datasets.len()
+    );
 
     // download stocks daily and basic and write local files
+    checkpoint::install_ctrl_c_handler();
+    let mut checkpoint = Checkpoint::open(&date_dir, header)?;
     download_stocks_daily(
         &date_dir,
-        &token,
-        &stocks_basic,
-        &earliest_trade_date,
-        &latest_trade_date,
-        config.download_type,
+        &source,
+        &plan,
+        &type_plans,
+        &datasets,
+        &mut checkpoint,
+        config.concurrency,
     )?;
+    finish_download(&date_dir, &datasets, config.strict)?;
+    checkpoint.remove()?;
+    match tushare {
+        Some(client) => info!(
+            "download finish, throttled by rate limit {:?}",
+            client.rate_limiter().throttled()
+        ),
+        None => info!("download finish"),
+    }
 
-    Ok((earliest_trade_date, latest_trade_date))
+    match config.run_mode {
+        RunMode::Full => Ok((start_trade_date, latest_trade_date)),
+        RunMode::Update => Ok((config.data_start_date.clone(), latest_trade_date)),
+    }
+}
+
+// listed funds of the statuses in the range
+fn crawl_funds(
+    source: &dyn DataSource,
+    config: &Config,
+    start_date: &str,
+    end_date: &str,
+) -> Result<Vec<FundBasic>, Box<dyn Error>> {
+    Ok(source
+        .fund_basic()?
+        .into_iter()
+        .filter(|f| {
+            config.list_statuses.iter().any(|s| s.code() == f.status)
+                && listed_in_range(&f.list_date, f.delist_date.as_deref(), start_date, end_date)
+        })
+        .collect())
 }
 
-fn crawl_trade_cal(config: &Config) -> Result<(String, String), Box<dyn std::error::Error>> {
-    let mut params: HashMap<String, String> = HashMap::new();
-    params.insert("exchange".to_owned(), "SSE".to_owned());
-    params.insert("start_date".to_owned(), config.data_start_date.to_owned());
-    params.insert("end_date".to_owned(), config.data_end_date.to_owned());
-    params.insert("is_open".to_owned(), "1".to_owned());
-    let api_params = TushareRESTfulAPI {
-        api_name: String::from("trade_cal"),
-        token: config.tushare_token.to_owned(),
-        params: params,
-        fields: String::from(""),
+// stock groups datasets are in the plan, others take one code or one trade date every call.
+// codes are stocks, indexes and funds. reports are selected by ann_date, a full download
+// looks back for the reports known at the start date, an append only takes the new ones.
+// all history of an api without date params is kept by date
+fn type_plans(
+    plan: &DownloadPlan,
+    datasets: &[&'static Dataset],
+    [ts_codes, index_codes, fund_codes]: [&[String]; 3],
+    trade_dates: &[String],
+    [start_trade_date, latest_trade_date]: [&str; 2],
+    append: bool,
+) -> Result<HashMap<&'static str, TypePlan>, Box<dyn Error>> {
+    let one_each =
+        |codes: &[String]| -> Vec<Vec<String>> { codes.iter().map(|c| vec![c.clone()]).collect() };
+    let window_from = |start_date: &str| {
+        vec![DateWindow {
+            start_date: start_date.to_owned(),
+            end_date: latest_trade_date.to_owned(),
+            trade_days: plan.trade_days,
+        }]
     };
+    let mut type_plans: HashMap<&'static str, TypePlan> = HashMap::new();
+    for dataset in datasets {
+        let type_plan = match dataset.partition {
+            Partition::PerStock(Calls::StockGroups) => continue,
+            Partition::PerStock(Calls::OneCode(codes)) => TypePlan {
+                groups: one_each(match codes {
+                    Codes::Stocks => ts_codes,
+                    Codes::Indexes => index_codes,
+                    Codes::Funds => fund_codes,
+                }),
+                windows: plan.windows.clone(),
+            },
+            Partition::PerStock(Calls::Lookback) => TypePlan {
+                groups: one_each(ts_codes),
+                windows: window_from(&if append {
+                    start_trade_date.to_owned()
+                } else {
+                    financial_start_date(start_trade_date)?
+                }),
+            },
+            Partition::PerStock(Calls::History) => TypePlan {
+                groups: one_each(ts_codes),
+                windows: window_from(start_trade_date),
+            },
+            Partition::PerDate => TypePlan {
+                groups: one_each(trade_dates),
+                windows: window_from(start_trade_date),
+            },
+        };
+        type_plans.insert(dataset.name, type_plan);
+    }
+    Ok(type_plans)
+}
 
-    let api_params_json = serde_json::to_string(&api_params).unwrap();
-    debug!("{}", api_params_json);
+// calls of all datasets, stock groups datasets by the plan and others by their own plans
+fn download_calls(
+    plan: &DownloadPlan,
+    type_plans: &HashMap<&'static str, TypePlan>,
+    datasets: &[&'static Dataset],
+) -> usize {
+    datasets
+        .iter()
+        .map(|dataset| match dataset.partition {
+            Partition::PerStock(Calls::StockGroups) => plan.calls(),
+            _ => type_plans
+                .get(dataset.name)
+                .map(|p| p.groups.len() * p.windows.len())
+                .unwrap_or_default(),
+        })
+        .sum()
+}
 
-    let client = reqwest::blocking::Client::new();
-    let res = client
-        .post("http://api.waditu.com")
-        .body(api_params_json)
-        .send()?;
+enum UpdatePlan {
+    // latest stored trade date
+    UpToDate(String),
+    // first missing calendar day, missing trade dates, new date dir,
+    // finished date dir to move into it (None when resuming)
+    Missing(String, Vec<String>, PathBuf, Option<PathBuf>),
+}
 
-    if !res.status().is_success() {
-        return Err(Box::new(MyError(String::from(
-            "get trade cal res status NOT 200!",
-        ))));
+// find the latest finished date dir, and trade days after it until end_date.
+fn prepare_update(
+    source: &dyn DataSource,
+    data_dir: &Path,
+    end_date: &str,
+) -> Result<UpdatePlan, Box<dyn Error>> {
+    // an update interrupted before its rename is still in the stored date dir
+    if let Some((unfinished_dir, header)) = find_unfinished_update(data_dir)? {
+        info!("resume unfinished update in {:?}", unfinished_dir);
+        let trade_dates = crawl_open_trade_dates(source, &header.start_date, &header.end_date)?;
+        let date_dir = data_dir.join(&header.end_date);
+        let update_from_dir = if unfinished_dir == date_dir {
+            None
+        } else {
+            Some(unfinished_dir)
+        };
+        return Ok(UpdatePlan::Missing(
+            header.start_date,
+            trade_dates,
+            date_dir,
+            update_from_dir,
+        ));
     }
 
-    let res_text_str = res.text()?;
+    let latest_date_dir = match find_latest_date_dir(data_dir)? {
+        Some(latest_date_dir) => latest_date_dir,
+        None => {
+            return Err(Box::new(MyError(format!(
+                "no finished data in {:?} to update, run full mode first",
+                data_dir
+            ))))
+        }
+    };
+    let stored_trade_date = latest_date_dir
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    info!("latest stored trade date: {}", stored_trade_date);
+
+    let next_date = NaiveDate::parse_from_str(&stored_trade_date, "%Y%m%d")?
+        .succ()
+        .format("%Y%m%d")
+        .to_string();
+    if next_date.as_str() > end_date {
+        return Ok(UpdatePlan::UpToDate(stored_trade_date));
+    }
+    let trade_dates = crawl_open_trade_dates(source, &next_date, end_date)?;
+    if trade_dates.is_empty() {
+        return Ok(UpdatePlan::UpToDate(stored_trade_date));
+    }
+    let latest_trade_date = trade_dates.iter().max().unwrap().to_owned();
+    info!(
+        "missing {} trade days: {} - {}",
+        trade_dates.len(),
+        trade_dates.iter().min().unwrap(),
+        latest_trade_date
+    );
+
+    Ok(UpdatePlan::Missing(
+        next_date,
+        trade_dates,
+        data_dir.join(&latest_trade_date),
+        Some(latest_date_dir),
+    ))
+}
 
-    let api_res: serde_json::Value = serde_json::from_str(&res_text_str)?;
-    if api_res["code"] != 0 {
-        return Err(Box::new(MyError(String::from(format!(
-            "get trade cal api return code != 0, ={}, request_id: {} , msg: {}",
-            api_res["code"], api_res["request_id"], api_res["msg"]
-        )))));
+// the finished date dir is renamed to the latest trade date and its _SUCCESS removed,
+// so an interrupted update never looks finished. the append checkpoint is written first,
+// so the dir is found as an unfinished update after any step, see prepare_update.
+fn start_update(
+    from_date_dir: &Path,
+    date_dir: &Path,
+    header: &CheckpointHeader,
+) -> Result<(), Box<dyn Error>> {
+    Checkpoint::open(from_date_dir, header.clone())?;
+    let success_file = from_date_dir.join("_SUCCESS");
+    if success_file.exists() {
+        fs::remove_file(success_file)?;
     }
+    if from_date_dir != date_dir {
+        fs::rename(from_date_dir, date_dir)?;
+    }
+    init_sub_dirs(date_dir)
+}
 
-    let api_data = &api_res["data"];
-    if api_data["has_more"] != false {
-        return Err(Box::new(MyError(String::from("has more?!"))));
+// date dirs are named by yyyymmdd, finished one has _SUCCESS
+fn find_latest_date_dir(data_dir: &Path) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let date_dirs = list_date_dirs(data_dir)?;
+    Ok(date_dirs
+        .into_iter()
+        .filter(|d| d.join("_SUCCESS").exists())
+        .max())
+}
+
+// the latest date dir with an unfinished update checkpoint
+fn find_unfinished_update(
+    data_dir: &Path,
+) -> Result<Option<(PathBuf, CheckpointHeader)>, Box<dyn Error>> {
+    let mut date_dirs = list_date_dirs(data_dir)?;
+    date_dirs.sort();
+    for date_dir in date_dirs.into_iter().rev() {
+        if date_dir.join("_SUCCESS").exists() {
+            continue;
+        }
+        if let Some(header) = Checkpoint::read_header(&date_dir) {
+            if header.append {
+                return Ok(Some((date_dir, header)));
+            }
+        }
     }
+    Ok(None)
+}
 
-    let items = &api_data["items"].as_array().unwrap();
+fn list_date_dirs(data_dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut date_dirs: Vec<PathBuf> = vec![];
+    for entry in fs::read_dir(data_dir)? {
+        let path = entry?.path();
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue,
+        };
+        if name.len() != 8 || !name.chars().all(|c| c.is_ascii_digit()) || !path.is_dir() {
+            continue;
+        }
+        date_dirs.push(path);
+    }
+    Ok(date_dirs)
+}
 
-    let mut cal_date_vec: Vec<&str> = Vec::new();
-    for i in items.iter() {
-        cal_date_vec.push(i[1].as_str().unwrap());
+fn crawl_trade_cal(
+    source: &dyn DataSource,
+    start_date: &str,
+    end_date: &str,
+) -> Result<(String, String, Vec<String>), Box<dyn std::error::Error>> {
+    let cal_date_vec = crawl_open_trade_dates(source, start_date, end_date)?;
+    if cal_date_vec.is_empty() {
+        return Err(Box::new(MyError(format!(
+            "no trade date between {} and {}",
+            start_date, end_date
+        ))));
     }
 
     let earliest_trade_date = cal_date_vec.iter().min().unwrap();
     let latest_trade_date = cal_date_vec.iter().max().unwrap();
 
     Ok((
-        String::from(earliest_trade_date.clone()),
-        String::from(latest_trade_date.clone()),
+        earliest_trade_date.to_owned(),
+        latest_trade_date.to_owned(),
+        cal_date_vec,
     ))
 }
 
+// SSE open days between start_date and end_date
+fn crawl_open_trade_dates(
+    source: &dyn DataSource,
+    start_date: &str,
+    end_date: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let trading_calendar = source.trade_calendar("SSE", start_date, end_date)?;
+    Ok(trading_calendar
+        .open_days(start_date, end_date)
+        .map(|d| d.to_owned())
+        .collect())
+}
+
 fn init_dir(date_dir: &PathBuf) -> Result<(), Box<dyn Error>> {
     debug!("{:?}", date_dir);
 
@@ -136,91 +583,81 @@ fn init_dir(date_dir: &PathBuf) -> Result<(), Box<dyn Error>> {
     }
     fs::create_dir(&date_dir).unwrap();
 
-    let daily_data_dir = date_dir.join("daily_data");
-    fs::create_dir(&daily_data_dir).unwrap();
+    init_sub_dirs(date_dir)
+}
+
+fn init_sub_dirs(date_dir: &Path) -> Result<(), Box<dyn Error>> {
+    for dataset in &DATASETS {
+        fs::create_dir_all(date_dir.join(dataset.dir))?;
+    }
 
-    let daily_basic_data_dir = date_dir.join("daily_basic_data");
-    fs::create_dir(&daily_basic_data_dir).unwrap();
+    let trade_cal_dir = date_dir.join("trade_cal");
+    fs::create_dir_all(&trade_cal_dir)?;
 
     Ok(())
 }
 
-fn crawl_stocks_basic(
-    token: &str,
-    exchange: &str,
-    market: &str,
-) -> Result<Vec<StockBasic>, Box<dyn std::error::Error>> {
-    let mut params: HashMap<String, String> = HashMap::new();
-    params.insert("exchange".to_owned(), exchange.to_owned());
-    params.insert("market".to_owned(), market.to_owned());
-    params.insert("list_status".to_owned(), "L".to_owned());
-    let api_params = TushareRESTfulAPI {
-        api_name: String::from("stock_basic"),
-        token: token.to_owned(),
-        params: params,
-        fields: String::from("ts_code, symbol, name, area, industry, fullname, enname, cnspell, market, exchange, curr_type, list_status, list_date, delist_date, is_hs"),
+// merge rows into one file: old rows between start_date and end_date are replaced,
+// then all rows are sorted by the date column. so appending an update, resuming a download,
+// or windows arriving in any order never write one day twice.
+// rows are written to a hidden temp file renamed over the old one, an exit never cuts a file.
+fn merge_data_file(
+    file_name: &Path,
+    header: &str,
+    rows: Vec<String>,
+    date_column: usize,
+    start_date: &str,
+    end_date: &str,
+) -> Result<(), Box<dyn Error>> {
+    let date = |row: &str| {
+        row.split('\t')
+            .nth(date_column)
+            .unwrap_or_default()
+            .to_owned()
     };
 
-    let api_params_json = serde_json::to_string(&api_params).unwrap();
-    debug!("{}", api_params_json);
-
-    let client = reqwest::blocking::Client::new();
-    let res = client
-        .post("http://api.waditu.com")
-        .body(api_params_json)
-        .send()?;
-
-    if !res.status().is_success() {
-        return Err(Box::new(MyError(String::from(
-            "get trade cal res status NOT 200!",
-        ))));
+    let mut all_rows: Vec<String> = vec![];
+    if file_name.exists() {
+        let content = fs::read_to_string(file_name)?;
+        for row in content.lines().skip(1) {
+            let date = date(row);
+            if date.as_str() < start_date || date.as_str() > end_date {
+                all_rows.push(row.to_owned());
+            }
+        }
     }
-
-    let res_text_str = res.text()?;
-
-    let api_res: serde_json::Value = serde_json::from_str(&res_text_str)?;
-    if api_res["code"] != 0 {
-        return Err(Box::new(MyError(String::from(format!(
-            "get trade cal return code != 0, ={}, request_id: {} , msg: {}",
-            api_res["code"], api_res["request_id"], api_res["msg"]
-        )))));
-    }
-
-    let api_data = &api_res["data"];
-    if api_data["has_more"] != false {
-        return Err(Box::new(MyError(String::from("has more?!"))));
-    }
-
-    let items = &api_data["items"].as_array().unwrap();
-    let mut stocks_base_vec: Vec<StockBasic> = Vec::new();
-    for i in items.iter() {
-        let stock_basic = StockBasic {
-            ts_code: i[0].as_str().unwrap().to_owned(),
-            symbol: i[1].as_str().unwrap().to_owned(),
-            name: i[2].as_str().unwrap().to_owned(),
-            area: i[3].as_str().unwrap().to_owned(),
-            industry: i[4].as_str().unwrap().to_owned(),
-            fullname: i[5].as_str().unwrap().to_owned(),
-            enname: i[6].as_str().unwrap().to_owned(),
-            cnspell: i[7].as_str().unwrap().to_owned(),
-            market: i[8].as_str().unwrap().to_owned(),
-            exchange: i[9].as_str().unwrap().to_owned(),
-            curr_type: i[10].as_str().unwrap().to_owned(),
-            list_status: i[11].as_str().unwrap().to_owned(),
-            list_date: i[12].as_str().unwrap().to_owned(),
-            delist_date: if i[13].is_null() {
-                None
-            } else {
-                Some(i[13].as_str().unwrap().to_owned())
-            },
-            is_hs: i[14].as_str().unwrap().to_owned(),
-        };
-        stocks_base_vec.push(stock_basic);
+    all_rows.extend(rows);
+    all_rows.sort_by_key(|row| date(row));
+
+    let tmp_file_name = file_name.with_file_name(format!(
+        ".{}.tmp",
+        file_name.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let _writing = checkpoint::write_guard();
+    let mut file = fs::File::create(&tmp_file_name)?;
+    writeln!(&mut file, "{}", header)?;
+    for row in all_rows {
+        writeln!(&mut file, "{}", row)?;
     }
+    file.sync_all()?;
+    fs::rename(&tmp_file_name, file_name)?;
+    Ok(())
+}
 
-    debug!("{:?}", stocks_base_vec[0]);
-
-    Ok(stocks_base_vec)
+// listed on or before end_date, and not delisted before start_date
+fn listed_in_range(
+    list_date: &str,
+    delist_date: Option<&str>,
+    start_date: &str,
+    end_date: &str,
+) -> bool {
+    if !list_date.is_empty() && list_date > end_date {
+        return false;
+    }
+    match delist_date {
+        Some(delist_date) => delist_date >= start_date,
+        None => true,
+    }
 }
 
 fn write_stocks_list(
@@ -254,301 +691,363 @@ fn read_stocks_list(file_name: &PathBuf) -> Result<Vec<StockBasic>, MyError> {
     Ok(a_vec)
 }
 
-// max crawl months is 23, if want to crawl 10 codes everytime.
+// the plan decides groups of codes and date windows of every call
 fn download_stocks_daily(
-    date_dir: &PathBuf,
-    token: &str,
-    stocks_basic: &Vec<StockBasic>,
-    start_date: &str,
-    end_date: &str,
-    download_type: DownloadType,
+    date_dir: &Path,
+    source: &Arc<dyn DataSource>,
+    plan: &DownloadPlan,
+    type_plans: &HashMap<&'static str, TypePlan>,
+    datasets: &[&'static Dataset],
+    checkpoint: &mut Checkpoint,
+    concurrency: usize,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    info!("will download {} stocks daily", stocks_basic.len());
-    let max_codes = 10;
-    let mut ts_code_grouped: Vec<Vec<String>> = vec![];
-    let mut current_ts_codes_grouped: Vec<String> = vec![];
-    for stock_basic in stocks_basic {
-        if current_ts_codes_grouped.len() == max_codes {
-            ts_code_grouped.push(current_ts_codes_grouped);
-            current_ts_codes_grouped = vec![];
-            current_ts_codes_grouped.push(stock_basic.ts_code.clone());
-        } else {
-            current_ts_codes_grouped.push(stock_basic.ts_code.clone());
-        }
-    }
-    if !current_ts_codes_grouped.is_empty() {
-        ts_code_grouped.push(current_ts_codes_grouped);
-    }
-
-    if download_type == DownloadType::All || download_type == DownloadType::Daily {
-        let daily_data_dir = date_dir.join("daily_data");
-        for ts_codes_group in ts_code_grouped.clone() {
-            let stocks_daily_vec =
-                crawl_stocks_daily(token, ts_codes_group.clone(), start_date, end_date).unwrap();
-            for ts_code in ts_codes_group {
-                let file_name = daily_data_dir.join(&ts_code);
-                debug!("{:?}", file_name);
-                let one_stock_daily: Vec<StockDaily> = stocks_daily_vec
-                    .iter()
-                    .filter(|s| s.ts_code == ts_code.to_string())
-                    .cloned()
-                    .collect();
-                // write one stock daily data
-                let mut file = fs::File::create(file_name).unwrap();
-                write!(&mut file, "ts_code\ttrade_date\topen\thigh\tlow\tclose\tpre_close\tchange\tpct_chg\tvol\tamount\n").unwrap();
-                for stock_daily in one_stock_daily {
-                    let stock_daily_string = stock_daily.to_string();
-                    write!(&mut file, "{}{}", stock_daily_string, "\n").unwrap();
+    info!("will download {} stocks daily", plan.stocks);
+
+    // groups not finished in checkpoint, datasets not in stock groups have their own plans
+    let mut tasks: Vec<GroupTask> = vec![];
+    let no_plan = TypePlan {
+        groups: vec![],
+        windows: vec![],
+    };
+    for dataset in datasets {
+        let (groups, windows) = match dataset.partition {
+            Partition::PerStock(Calls::StockGroups) => (&plan.groups, &plan.windows),
+            _ => {
+                let type_plan = type_plans.get(dataset.name).unwrap_or(&no_plan);
+                (&type_plan.groups, &type_plan.windows)
+            }
+        };
+        for window in windows {
+            for ts_codes_group in groups {
+                let task = GroupTask {
+                    dataset,
+                    ts_codes: ts_codes_group.clone(),
+                    window: window.clone(),
+                };
+                if !checkpoint.is_finished(&task.checkpoint_key(), &task.ts_codes) {
+                    tasks.push(task);
                 }
             }
         }
     }
-
-    if download_type == DownloadType::All || download_type == DownloadType::DailyBasic {
-        let daily_basic_data_dir = date_dir.join("daily_basic_data");
-        for ts_codes_group in ts_code_grouped.clone() {
-            let stocks_daily_basic_vec =
-                crawl_stocks_daily_basic(token, ts_codes_group.clone(), start_date, end_date)
-                    .unwrap();
-            for ts_code in ts_codes_group {
-                let file_name = daily_basic_data_dir.join(&ts_code);
-                debug!("{:?}", file_name);
-                let one_stock_basic_daily: Vec<StockDailyBasic> = stocks_daily_basic_vec
-                    .iter()
-                    .filter(|s| s.ts_code == ts_code.to_string())
-                    .cloned()
-                    .collect();
-                // write one stock daily basic data
-                let mut file = fs::File::create(file_name).unwrap();
-                write!(&mut file, "ts_code\ttrade_date\tclose\tturnover_rate\tturnover_rate_f\tvolume_ratio\tpe\tpe_ttm\tpb\tps\tps_ttm\tdv_ratio\tdv_ttm\ttotal_share\tfloat_share\tfree_share\ttotal_mv\tcirc_mv\tlimit_status\n").unwrap();
-                for stock_daily_basic in one_stock_basic_daily {
-                    let stock_daily_basic_string = stock_daily_basic.to_string();
-                    write!(&mut file, "{}{}", stock_daily_basic_string, "\n").unwrap();
-                }
-            }
+    info!(
+        "{} groups to download, concurrency {}",
+        tasks.len(),
+        concurrency
+    );
+
+    let mut on_group_data = |task: &GroupTask, group_data: GroupData| {
+        write_group_data(date_dir, task, &group_data)?;
+        checkpoint.finish(&task.checkpoint_key(), &task.ts_codes)?;
+        stop_if_requested()
+    };
+    if concurrency > 1 {
+        download_groups_concurrently(source, tasks, concurrency, &mut on_group_data)?;
+    } else {
+        for task in tasks {
+            let group_data = crawl_group(source.as_ref(), &task)?;
+            on_group_data(&task, group_data)?;
         }
     }
+    Ok(())
+}
+
+// validate the downloaded data and write _SUCCESS, strict refuses data with any issue
+fn finish_download(
+    date_dir: &Path,
+    datasets: &[&'static Dataset],
+    strict: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let report = validate::validate(date_dir)?;
+    report.write(date_dir)?;
+    if report.passed {
+        info!("validation passed: {}", report.summary());
+    } else if strict {
+        return Err(Box::new(MyError(format!(
+            "validation failed, see {}: {}",
+            validate::REPORT_FILE,
+            report.summary()
+        ))));
+    } else {
+        warn!(
+            "validation found issues, see {}: {}",
+            validate::REPORT_FILE,
+            report.summary()
+        );
+    }
 
     // write finish file _SUCCESS
     let mut file = fs::File::create(date_dir.join("_SUCCESS")).unwrap();
-    let mut result_vec: Vec<&str> = vec![];
-    if download_type == DownloadType::All {
-        result_vec = vec!["daily", "daily_basic"];
-    } else if download_type == DownloadType::Daily {
-        result_vec = vec!["daily"];
-    } else if download_type == DownloadType::Daily {
-        result_vec = vec!["daily_basic"];
-    }
-    let result_str = result_vec.join("\n");
+    let names: Vec<&str> = datasets.iter().map(|d| d.name).collect();
+    let result_str = names.join("\n");
     file.write_all(result_str.as_bytes()).unwrap();
 
     Ok(())
 }
 
-// 每分钟内最多调取500次，每次5000条数据. so max crawl months is 23, if want to crawl 10 codes everytime.
-fn crawl_stocks_daily(
-    token: &str,
-    ts_codes: Vec<String>,
-    start_date: &str,
-    end_date: &str,
-) -> Result<Vec<StockDaily>, Box<dyn std::error::Error>> {
-    let mut params: HashMap<String, String> = HashMap::new();
-    params.insert("ts_code".to_owned(), ts_codes.join(",").to_owned());
-    params.insert("start_date".to_owned(), start_date.to_owned());
-    params.insert("end_date".to_owned(), end_date.to_owned());
-    let api_params = TushareRESTfulAPI {
-        api_name: String::from("daily"),
-        token: token.to_owned(),
-        params: params,
-        fields: String::from(
-            "ts_code, trade_date, open, high, low, close, pre_close, change, pct_chg, vol, amount",
-        ),
-    };
-
-    let api_params_json = serde_json::to_string(&api_params).unwrap();
-    debug!("{}", api_params_json);
+// groups and windows of one dataset not in the stocks plan
+struct TypePlan {
+    groups: Vec<Vec<String>>,
+    windows: Vec<DateWindow>,
+}
 
-    let client = reqwest::blocking::Client::new();
-    let res = client
-        .post("http://api.waditu.com")
-        .body(api_params_json)
-        .send()?;
+// one api call for a group of ts_codes in a date window,
+// the group of a per date dataset is its trade date
+struct GroupTask {
+    dataset: &'static Dataset,
+    ts_codes: Vec<String>,
+    window: DateWindow,
+}
 
-    if !res.status().is_success() {
-        return Err(Box::new(MyError(String::from(
-            "get trade cal res status NOT 200!",
-        ))));
+impl GroupTask {
+    fn checkpoint_key(&self) -> String {
+        format!("{}/{}", self.dataset.name, self.window.start_date)
     }
+}
 
-    let res_text_str = res.text()?;
+// stored rows of one call, cells in the order of the dataset fields
+type GroupData = Vec<Vec<String>>;
 
-    let api_res: serde_json::Value = serde_json::from_str(&res_text_str)?;
-    // debug!("{}", api_res);
+// crawl error is sent across threads as string
+type GroupResult = (GroupTask, Result<GroupData, String>);
 
-    if api_res["code"] != 0 {
-        return Err(Box::new(MyError(String::from(format!(
-            "get trade cal return code != 0, ={}, request_id: {} , msg: {}",
-            api_res["code"], api_res["request_id"], api_res["msg"]
-        )))));
+fn crawl_group(source: &dyn DataSource, task: &GroupTask) -> Result<GroupData, Box<dyn Error>> {
+    let rows = source.dataset_rows(
+        task.dataset,
+        &task.ts_codes,
+        &task.window.start_date,
+        &task.window.end_date,
+    )?;
+    let rows = good_rows(task, rows);
+    debug!("{} {} rows", task.dataset.name, rows.len());
+    Ok(rows)
+}
+
+// stored rows of the task window, a row without a required field is dropped,
+// the others of the call are kept
+fn good_rows(task: &GroupTask, source_rows: DatasetRows) -> GroupData {
+    let dataset = task.dataset;
+    let date_column = dataset.column(dataset.date_field).unwrap_or_default();
+    let mut rows: GroupData = vec![];
+    let mut dropped: Vec<String> = vec![];
+    for row in source_rows {
+        match row {
+            Ok(row) => {
+                let date = row[date_column].as_str();
+                if date >= task.window.start_date.as_str() && date <= task.window.end_date.as_str()
+                {
+                    rows.push(row);
+                }
+            }
+            Err(e) => dropped.push(e),
+        }
+    }
+    if let Some(e) = dropped.first() {
+        warn!(
+            "{} {:?} dropped {} rows, first: {}",
+            dataset.name,
+            task.ts_codes,
+            dropped.len(),
+            e
+        );
     }
+    rows
+}
 
-    let api_data = &api_res["data"];
-    if api_data["has_more"] != false {
-        return Err(Box::new(MyError(String::from("has more?!"))));
+// write rows into the file of every stock of the group, or the file of the trade date
+fn write_group_data(
+    date_dir: &Path,
+    task: &GroupTask,
+    group_data: &GroupData,
+) -> Result<(), Box<dyn Error>> {
+    let dataset = task.dataset;
+    let date_column = dataset.column(dataset.date_field).unwrap_or_default();
+    let ts_code_column = dataset.column("ts_code").unwrap_or_default();
+    for file_key in &task.ts_codes {
+        let file_rows = group_data.iter().filter(|row| match dataset.partition {
+            Partition::PerStock(_) => &row[ts_code_column] == file_key,
+            Partition::PerDate => &row[date_column] == file_key,
+        });
+        // the last row of the same key is kept
+        let mut keyed: HashMap<Vec<&str>, usize> = HashMap::new();
+        let mut rows: Vec<String> = vec![];
+        for row in file_rows {
+            let line = row.join("\t");
+            match keyed.get(&dataset.key(row)) {
+                Some(&i) => rows[i] = line,
+                None => {
+                    keyed.insert(dataset.key(row), rows.len());
+                    rows.push(line);
+                }
+            }
+        }
+        let file_name = date_dir.join(dataset.dir).join(file_key);
+        debug!("{:?}", file_name);
+        merge_data_file(
+            &file_name,
+            &dataset.header(),
+            rows,
+            date_column,
+            &task.window.start_date,
+            &task.window.end_date,
+        )?;
     }
+    Ok(())
+}
 
-    let items = &api_data["items"].as_array().unwrap();
-    let mut stocks_daily_vec: Vec<StockDaily> = Vec::new();
-    for i in items.iter() {
-        let stock_daily = StockDaily {
-            ts_code: i[0].as_str().unwrap().to_owned(),
-            trade_date: i[1].as_str().unwrap().to_owned(),
-            open: i[2].as_f64().unwrap().to_owned(),
-            high: i[3].as_f64().unwrap().to_owned(),
-            low: i[4].as_f64().unwrap().to_owned(),
-            close: i[5].as_f64().unwrap().to_owned(),
-            pre_close: i[6].as_f64().unwrap().to_owned(),
-            change: i[7].as_f64().unwrap().to_owned(),
-            pct_chg: i[8].as_f64().unwrap().to_owned(),
-            vol: i[9].as_f64().unwrap().to_owned(),
-            amount: i[10].as_f64().unwrap().to_owned(),
+// crawl groups in a pool of `concurrency` worker threads,
+// the shared tushare client's rate limiter keeps all of them under the api limit.
+// a thread pool of the blocking client is chosen over an async client: the api quota,
+// not threads, bounds the download, and a few workers already use all of it.
+// results are handled one by one here as they arrive, so files and checkpoint have one writer.
+// a group that panics is an error, every group must be handled to finish.
+fn download_groups_concurrently<F>(
+    source: &Arc<dyn DataSource>,
+    tasks: Vec<GroupTask>,
+    concurrency: usize,
+    on_group_data: &mut F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&GroupTask, GroupData) -> Result<(), Box<dyn Error>>,
+{
+    let spawned = tasks.len();
+    let queue = Arc::new(Mutex::new(VecDeque::from(tasks)));
+    // set after an error, workers take no more new groups
+    let stop = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel::<GroupResult>();
+    for _ in 0..concurrency.min(spawned) {
+        let source = source.clone();
+        let queue = queue.clone();
+        let stop = stop.clone();
+        let tx = tx.clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                let task = match queue.lock().map(|mut q| q.pop_front()) {
+                    Ok(Some(task)) => task,
+                    _ => return,
+                };
+                let group_data =
+                    panic::catch_unwind(AssertUnwindSafe(|| crawl_group(source.as_ref(), &task)))
+                        .map_err(|_| String::from("group task panicked"))
+                        .and_then(|r| r.map_err(|e| e.to_string()));
+                // receiver is gone after an error, nothing to do
+                if tx.send((task, group_data)).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    drop(tx);
+
+    // groups still in flight after an error are not waited for, they are not in checkpoint
+    let mut handled_groups = 0;
+    for (task, group_data) in rx {
+        let handled = match group_data {
+            Ok(group_data) => {
+                debug!("{} {:?} arrived", task.dataset.name, task.ts_codes);
+                on_group_data(&task, group_data)
+            }
+            Err(e) => Err(Box::new(MyError(e)) as Box<dyn Error>),
         };
-        stocks_daily_vec.push(stock_daily);
+        if handled.is_err() {
+            stop.store(true, Ordering::SeqCst);
+            return handled;
+        }
+        handled_groups += 1;
     }
-
-    debug!("{:?}", stocks_daily_vec[0]);
-
-    Ok(stocks_daily_vec)
+    if handled_groups != spawned {
+        return Err(Box::new(MyError(format!(
+            "{} of {} groups finished",
+            handled_groups, spawned
+        ))));
+    }
+    Ok(())
 }
 
-fn crawl_stocks_daily_basic(
-    token: &str,
-    ts_codes: Vec<String>,
-    start_date: &str,
-    end_date: &str,
-) -> Result<Vec<StockDailyBasic>, Box<dyn std::error::Error>> {
-    let mut params: HashMap<String, String> = HashMap::new();
-    params.insert("ts_code".to_owned(), ts_codes.join(",").to_owned());
-    params.insert("start_date".to_owned(), start_date.to_owned());
-    params.insert("end_date".to_owned(), end_date.to_owned());
-    let api_params = TushareRESTfulAPI {
-        api_name: String::from("daily_basic"),
-        token: token.to_owned(),
-        params: params,
-        fields: String::from(
-            "ts_code, trade_date, close, turnover_rate, turnover_rate_f, volume_ratio, pe, pe_ttm, pb, ps, ps_ttm, dv_ratio, dv_ttm, total_share, float_share, free_share, total_mv, circ_mv, limit_status",
-        ),
-    };
-
-    let api_params_json = serde_json::to_string(&api_params).unwrap();
-    debug!("{}", api_params_json);
-
-    let client = reqwest::blocking::Client::new();
-    let res = client
-        .post("http://api.waditu.com")
-        .body(api_params_json)
-        .send()?;
-
-    if !res.status().is_success() {
+// ctrl-c stops between groups, the checkpoint is consistent then
+fn stop_if_requested() -> Result<(), Box<dyn Error>> {
+    if checkpoint::stop_requested() {
         return Err(Box::new(MyError(String::from(
-            "get trade cal res status NOT 200!",
+            "stopped by ctrl-c, rerun to resume from checkpoint",
         ))));
     }
+    Ok(())
+}
 
-    let res_text_str = res.text()?;
-
-    let api_res: serde_json::Value = serde_json::from_str(&res_text_str)?;
-    // debug!("{}", api_res);
-
-    if api_res["code"] != 0 {
-        return Err(Box::new(MyError(String::from(format!(
-            "get trade cal return code != 0, ={}, request_id: {} , msg: {}",
-            api_res["code"], api_res["request_id"], api_res["msg"]
-        )))));
+fn write_index_list(
+    file_name: &Path,
+    index_basic_vec: &[IndexBasic],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = fs::File::create(file_name)?;
+    writeln!(
+        &mut file,
+        "ts_code\tname\tmarket\tpublisher\tcategory\tbase_date\tbase_point\tlist_date"
+    )?;
+    for index_basic in index_basic_vec {
+        writeln!(&mut file, "{}", index_basic)?;
     }
 
-    let api_data = &api_res["data"];
-    if api_data["has_more"] != false {
-        return Err(Box::new(MyError(String::from("has more?!"))));
-    }
+    Ok(())
+}
 
-    let items = &api_data["items"].as_array().unwrap();
-    let mut stocks_daily_basic_vec: Vec<StockDailyBasic> = Vec::new();
-    for i in items.iter() {
-        debug!("{:?}", i);
-        let stock_daily_basic = StockDailyBasic {
-            ts_code: i[0].as_str().unwrap().to_owned(),
-            trade_date: i[1].as_str().unwrap().to_owned(),
-            close: i[2].as_f64().unwrap().to_owned(),
-            turnover_rate: i[3].as_f64().unwrap().to_owned(),
-            turnover_rate_f: if i[4].is_null() {
-                None
-            } else {
-                Some(i[4].as_f64().unwrap().to_owned())
-            },
-            volume_ratio: if i[5].is_null() {
-                None
-            } else {
-                Some(i[5].as_f64().unwrap().to_owned())
-            },
-            pe: if i[6].is_null() {
-                None
-            } else {
-                Some(i[6].as_f64().unwrap().to_owned())
-            },
-            pe_ttm: if i[7].is_null() {
-                None
-            } else {
-                Some(i[7].as_f64().unwrap().to_owned())
-            },
-            pb: if i[8].is_null() {
-                None
-            } else {
-                Some(i[8].as_f64().unwrap().to_owned())
-            },
-            ps: if i[9].is_null() {
-                None
-            } else {
-                Some(i[9].as_f64().unwrap().to_owned())
-            },
-            ps_ttm: if i[10].is_null() {
-                None
-            } else {
-                Some(i[10].as_f64().unwrap().to_owned())
-            },
-            dv_ratio: if i[11].is_null() {
-                None
-            } else {
-                Some(i[11].as_f64().unwrap().to_owned())
-            },
-            dv_ttm: if i[12].is_null() {
-                None
-            } else {
-                Some(i[12].as_f64().unwrap().to_owned())
-            },
-            total_share: i[13].as_f64().unwrap().to_owned(),
-            float_share: i[14].as_f64().unwrap().to_owned(),
-            free_share: i[15].as_f64().unwrap().to_owned(),
-            total_mv: i[16].as_f64().unwrap().to_owned(),
-            circ_mv: i[17].as_f64().unwrap().to_owned(),
-            limit_status: if i[18].is_null() {
-                None
-            } else {
-                Some(i[18].as_i64().unwrap().to_owned())
-            },
-        };
-        stocks_daily_basic_vec.push(stock_daily_basic);
-    }
+// start date of a full financial download, FINANCIAL_LOOKBACK_YEARS before the first trade date
+fn financial_start_date(start_trade_date: &str) -> Result<String, Box<dyn Error>> {
+    let start_date = NaiveDate::parse_from_str(start_trade_date, "%Y%m%d")?;
+    let lookback_date = NaiveDate::from_ymd(
+        start_date.year() - FINANCIAL_LOOKBACK_YEARS,
+        start_date.month(),
+        1,
+    );
+    Ok(lookback_date.format("%Y%m%d").to_string())
+}
 
-    debug!("{:?}", stocks_daily_basic_vec[0]);
+fn write_funds_list(
+    file_name: &Path,
+    fund_basic_vec: &[FundBasic],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = fs::File::create(file_name)?;
+    writeln!(
+        &mut file,
+        "ts_code\tname\tmanagement\tfund_type\tinvest_type\tmarket\tstatus\tlist_date\tdelist_date"
+    )?;
+    for fund_basic in fund_basic_vec {
+        writeln!(&mut file, "{}", fund_basic)?;
+    }
 
-    Ok(stocks_daily_basic_vec)
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{DownloadType, Opt};
+    use crate::cassette::{Cassette, CassetteMode};
+    use crate::rate_limit::RateLimiter;
+    use crate::retry::RetryPolicy;
+    use crate::source::Source;
+    use crate::universe::Market;
+    use crate::{AccountTier, DownloadType, ListStatus, Opt, RunMode};
+    use std::time::Duration;
+
+    // calls of 689009.SH and 688981.SH from 20210901 to 20210910 without network
+    fn replay_client() -> TushareClient {
+        let cassette = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures")
+            .join("cassettes")
+            .join("crawl.json");
+        let cassette = Cassette::open(&CassetteMode::Replay(
+            cassette.to_string_lossy().to_string(),
+        ))
+        .unwrap();
+        TushareClient::new(
+            "",
+            crate::tushare::DEFAULT_API_URL,
+            Duration::from_secs(30),
+            Arc::new(RateLimiter::new(AccountTier::Points5000, HashMap::new())),
+            RetryPolicy::new(0),
+        )
+        .unwrap()
+        .with_cassette(Arc::new(cassette))
+    }
 
     #[test]
     #[ignore]
@@ -557,19 +1056,55 @@ mod tests {
             data_start_date: String::from("20210101"),
             data_end_date: String::from("20210912"),
             download_type: DownloadType::All,
+            run_mode: RunMode::Full,
+            tushare_api_url: None,
+            request_timeout: 30,
+            account_tier: AccountTier::Points5000,
+            rate_limit: vec![],
+            max_retries: 5,
+            concurrency: 8,
+            plan_only: false,
+            markets: "SSE:主板,SZSE:主板".parse().unwrap(),
+            list_status: vec![ListStatus::Listed],
+            index_codes: vec![String::from("000300.SH")],
+            source: Source::Tushare,
+            cassette: None,
+            strict: false,
+            command: None,
         };
         let config = Config::new(args).unwrap();
-        assert_eq!(crawl_trade_cal(&config).unwrap().1, "20210910");
+        let client = TushareClient::from_config(&config).unwrap();
+        assert_eq!(
+            crawl_trade_cal(&client, &config.data_start_date, &config.data_end_date)
+                .unwrap()
+                .1,
+            "20210910"
+        );
     }
 
     #[test]
     #[ignore]
     fn test_init_dir() {
-        env_logger::init();
+        let _ = env_logger::builder().is_test(true).try_init();
         let args = Opt {
             data_start_date: String::from("20210101"),
             data_end_date: String::from("20210912"),
             download_type: DownloadType::All,
+            run_mode: RunMode::Full,
+            tushare_api_url: None,
+            request_timeout: 30,
+            account_tier: AccountTier::Points5000,
+            rate_limit: vec![],
+            max_retries: 5,
+            concurrency: 8,
+            plan_only: false,
+            markets: "SSE:主板,SZSE:主板".parse().unwrap(),
+            list_status: vec![ListStatus::Listed],
+            index_codes: vec![String::from("000300.SH")],
+            source: Source::Tushare,
+            cassette: None,
+            strict: false,
+            command: None,
         };
         let config = Config::new(args).unwrap();
         let date_dir = Path::new(&config.data_dir).join("20990101".to_owned());
@@ -580,34 +1115,135 @@ mod tests {
     #[test]
     #[ignore]
     fn test_run() {
-        env_logger::init();
+        let _ = env_logger::builder().is_test(true).try_init();
         use chrono::offset::Local;
 
         let args = Opt {
             data_start_date: String::from("20210101"),
             data_end_date: Local::now().format("%Y%m%d").to_string(),
             download_type: DownloadType::All,
+            run_mode: RunMode::Full,
+            tushare_api_url: None,
+            request_timeout: 30,
+            account_tier: AccountTier::Points5000,
+            rate_limit: vec![],
+            max_retries: 5,
+            concurrency: 8,
+            plan_only: false,
+            markets: "SSE:主板,SZSE:主板".parse().unwrap(),
+            list_status: vec![ListStatus::Listed],
+            index_codes: vec![String::from("000300.SH")],
+            source: Source::Tushare,
+            cassette: None,
+            strict: false,
+            command: None,
         };
         let config = Config::new(args).unwrap();
 
         assert_eq!(run(&config).unwrap().0, "20210101");
     }
 
+    // run of 3 mock stocks from 20210901 to 20210910, recorded from bin/mock_tushare
+    #[test]
+    fn test_run_replay() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let data_dir = std::env::temp_dir().join("choose_some_test_run_replay");
+        if data_dir.exists() {
+            fs::remove_dir_all(&data_dir).unwrap();
+        }
+        fs::create_dir_all(&data_dir).unwrap();
+        let cassette = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures")
+            .join("cassettes")
+            .join("run.json");
+        let args = Opt {
+            data_start_date: String::from("20210901"),
+            data_end_date: String::from("20210910"),
+            download_type: DownloadType::All,
+            run_mode: RunMode::Full,
+            tushare_api_url: None,
+            request_timeout: 30,
+            account_tier: AccountTier::Points5000,
+            rate_limit: vec![],
+            max_retries: 0,
+            concurrency: 2,
+            plan_only: false,
+            markets: "SSE:主板,SZSE:主板".parse().unwrap(),
+            list_status: vec![ListStatus::Listed],
+            index_codes: vec![String::from("000300.SH")],
+            source: Source::Tushare,
+            cassette: Some(CassetteMode::Replay(cassette.to_string_lossy().to_string())),
+            strict: true,
+            command: None,
+        };
+        // the data dir is set below, Config::new only needs one
+        if std::env::var("DATA_DIR").is_err() {
+            std::env::set_var("DATA_DIR", &data_dir);
+        }
+        let mut config = Config::new(args).unwrap();
+        config.data_dir = data_dir.to_string_lossy().to_string();
+
+        assert_eq!(
+            run(&config).unwrap(),
+            ("20210901".to_owned(), "20210910".to_owned())
+        );
+        let date_dir = data_dir.join("20210910");
+        assert_eq!(
+            fs::read_to_string(date_dir.join("_SUCCESS")).unwrap(),
+            "daily\ndaily_basic"
+        );
+        assert!(!date_dir.join(checkpoint::CHECKPOINT_FILE).exists());
+        assert!(date_dir.join(validate::REPORT_FILE).exists());
+        assert!(calendar_file(&date_dir, "SSE").exists());
+        let ts_codes: Vec<String> = read_stocks_list(&date_dir.join("stocks_list"))
+            .unwrap()
+            .into_iter()
+            .map(|s| s.ts_code)
+            .collect();
+        assert_eq!(ts_codes, vec!["600000.SH", "600001.SH", "000001.SZ"]);
+        for dir in &["daily_data", "daily_basic_data"] {
+            for ts_code in &ts_codes {
+                let content = fs::read_to_string(date_dir.join(dir).join(ts_code)).unwrap();
+                // header and 8 trade days
+                assert_eq!(content.lines().count(), 9);
+            }
+        }
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
     #[test]
     #[ignore]
     fn test_get_stock_basic() {
-        env_logger::init();
+        let _ = env_logger::builder().is_test(true).try_init();
         use chrono::offset::Local;
 
         let args = Opt {
             data_start_date: String::from("20210101"),
             data_end_date: Local::now().format("%Y%m%d").to_string(),
             download_type: DownloadType::All,
+            run_mode: RunMode::Full,
+            tushare_api_url: None,
+            request_timeout: 30,
+            account_tier: AccountTier::Points5000,
+            rate_limit: vec![],
+            max_retries: 5,
+            concurrency: 8,
+            plan_only: false,
+            markets: "SSE:主板,SZSE:主板".parse().unwrap(),
+            list_status: vec![ListStatus::Listed],
+            index_codes: vec![String::from("000300.SH")],
+            source: Source::Tushare,
+            cassette: None,
+            strict: false,
+            command: None,
         };
         let config = Config::new(args).unwrap();
-        let token = config.tushare_token;
+        let client = TushareClient::from_config(&config).unwrap();
 
-        let result = crawl_stocks_basic(&token, "SSE", "主板").unwrap();
+        let result = client
+            .stocks_basic(&Market::new("SSE", "主板"), ListStatus::Listed)
+            .unwrap();
         let result_len = result.len();
         println!("{}", result_len);
         assert!(result_len >= 1);
@@ -616,19 +1252,36 @@ mod tests {
     #[test]
     #[ignore]
     fn test_write_stocks_basic() {
-        env_logger::init();
+        let _ = env_logger::builder().is_test(true).try_init();
         use chrono::offset::Local;
 
         let args = Opt {
             data_start_date: String::from("20210101"),
             data_end_date: Local::now().format("%Y%m%d").to_string(),
             download_type: DownloadType::All,
+            run_mode: RunMode::Full,
+            tushare_api_url: None,
+            request_timeout: 30,
+            account_tier: AccountTier::Points5000,
+            rate_limit: vec![],
+            max_retries: 5,
+            concurrency: 8,
+            plan_only: false,
+            markets: "SSE:主板,SZSE:主板".parse().unwrap(),
+            list_status: vec![ListStatus::Listed],
+            index_codes: vec![String::from("000300.SH")],
+            source: Source::Tushare,
+            cassette: None,
+            strict: false,
+            command: None,
         };
         let config = &Config::new(args).unwrap();
-        let token = config.tushare_token.clone();
+        let client = TushareClient::from_config(config).unwrap();
 
         let data_dir = Path::new(&config.data_dir);
-        let trade_date = crawl_trade_cal(config).unwrap().1;
+        let trade_date = crawl_trade_cal(&client, &config.data_start_date, &config.data_end_date)
+            .unwrap()
+            .1;
         let date_dir = data_dir.join(trade_date);
 
         // init dir
@@ -637,7 +1290,9 @@ mod tests {
         // wrtie stocks_list
         let file_name = date_dir.join("stocks_list");
 
-        let stocks_basic_vec = crawl_stocks_basic(&token, "SSE", "主板").unwrap();
+        let stocks_basic_vec = client
+            .stocks_basic(&Market::new("SSE", "主板"), ListStatus::Listed)
+            .unwrap();
         let result = write_stocks_list(&file_name, &stocks_basic_vec).unwrap();
         assert_eq!(result, ());
     }
@@ -645,76 +1300,355 @@ mod tests {
     #[test]
     #[ignore]
     fn test_read_stocks_list() {
-        env_logger::init();
+        let _ = env_logger::builder().is_test(true).try_init();
         let file_name = PathBuf::from("/Users/phoenix/data/20210917/stocks_list");
         assert!(read_stocks_list(&file_name).unwrap().len() > 1);
     }
 
+    // a group task of stocks from 20210901 to 20210910
+    fn group_task(name: &str, ts_codes: &[&str]) -> GroupTask {
+        GroupTask {
+            dataset: crate::dataset::dataset(name).unwrap(),
+            ts_codes: ts_codes.iter().map(|c| c.to_string()).collect(),
+            window: DateWindow {
+                start_date: "20210901".to_owned(),
+                end_date: "20210910".to_owned(),
+                trade_days: 8,
+            },
+        }
+    }
+
     #[test]
     fn test_crawl_stocks_daily() {
-        env_logger::init();
-        let args = Opt {
-            data_start_date: String::from("20210101"),
-            data_end_date: String::from("20210901"),
-            download_type: DownloadType::All,
-        };
-        let config = &Config::new(args).unwrap();
-        let token = config.tushare_token.clone();
-        let ts_codes = vec!["689009.SH".to_owned(), "688981.SH".to_owned()];
-        let start_date = "20210901";
-        let end_date = "20210910";
+        let _ = env_logger::builder().is_test(true).try_init();
+        let client = replay_client();
+
+        let task = group_task("daily", &["689009.SH", "688981.SH"]);
+        assert_eq!(crawl_group(&client, &task).unwrap().len(), 16);
 
-        let stocks_daily_vec = crawl_stocks_daily(&token, ts_codes, start_date, end_date);
-        assert!(stocks_daily_vec.unwrap().len() > 1);
+        let task = group_task("daily", &["600000.SH"]);
+        assert!(crawl_group(&client, &task).is_err());
     }
 
     #[test]
     fn test_crawl_stocks_daily_basic() {
-        env_logger::init();
-        let args = Opt {
-            data_start_date: String::from("20210101"),
-            data_end_date: String::from("20210901"),
-            download_type: DownloadType::All,
-        };
-        let config = &Config::new(args).unwrap();
-        let token = config.tushare_token.clone();
-        let ts_codes = vec!["689009.SH".to_owned(), "688981.SH".to_owned()];
-        let start_date = "20210901";
-        let end_date = "20210910";
+        let _ = env_logger::builder().is_test(true).try_init();
+        let client = replay_client();
+        let task = group_task("daily_basic", &["689009.SH", "688981.SH"]);
+        let dataset = task.dataset;
+
+        let rows = crawl_group(&client, &task).unwrap();
+        assert_eq!(rows.len(), 16);
+        let ts_code = dataset.column("ts_code").unwrap();
+        let pe = dataset.column("pe").unwrap();
+        assert!(rows
+            .iter()
+            .filter(|row| row[ts_code] == "689009.SH")
+            .all(|row| row[pe] == "none"));
+    }
 
-        let stocks_daily_basic_vec =
-            crawl_stocks_daily_basic(&token, ts_codes, start_date, end_date);
-        assert!(stocks_daily_basic_vec.unwrap().len() > 1);
+    #[test]
+    fn test_good_rows() {
+        let body = r#"{"request_id":"1","code":0,"msg":"","data":{"fields":["trade_date","ts_code","open","high","low","close","pre_close","change","pct_chg","vol","amount"],"items":[["20210901","000001.SZ",10,10.5,9.9,10.2,10,0.2,2,null,1000],["20210902","000001.SZ",10.2,10.6,10.1,null,10.2,0.1,0.98,100,1000],["20210903","000001.SZ",10.3,10.5,10.1,"x",10.3,0.1,0.97,100,1000],["20210913","000001.SZ",10.3,10.5,10.1,10.4,10.3,0.1,0.97,100,1000]],"has_more":false}}"#;
+        let (data, _) = crate::tushare::decode_response("daily", body).unwrap();
+        let task = group_task("daily", &["000001.SZ"]);
+        let source_rows = data
+            .items
+            .iter()
+            .filter_map(|item| task.dataset.row(&data.fields, item).transpose())
+            .collect();
+        let rows = good_rows(&task, source_rows);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0][task.dataset.column("trade_date").unwrap()],
+            "20210901"
+        );
+        assert_eq!(rows[0][task.dataset.column("vol").unwrap()], "none");
     }
 
     #[test]
     fn test_download_stocks_daily() {
-        env_logger::init();
-        let args = Opt {
-            data_start_date: String::from("20210101"),
-            data_end_date: String::from("20210917"),
-            download_type: DownloadType::All,
-        };
-        let config = &Config::new(args).unwrap();
-        let token = config.tushare_token.clone();
+        let _ = env_logger::builder().is_test(true).try_init();
+        let source: Arc<dyn DataSource> = Arc::new(replay_client());
         let start_date = "20210901";
-        let end_date = "20210917";
-        let date_dir = PathBuf::from(config.data_dir.clone() + "/20210917");
+        let end_date = "20210910";
+        let data_dir = std::env::temp_dir().join("choose_some_test_download_stocks_daily");
+        if data_dir.exists() {
+            fs::remove_dir_all(&data_dir).unwrap();
+        }
+        fs::create_dir_all(&data_dir).unwrap();
+        let date_dir = data_dir.join(end_date);
+        init_dir(&date_dir).unwrap();
 
-        let file_name = PathBuf::from("/Users/phoenix/data/20210917/stocks_list");
-        let stocks_basic = &read_stocks_list(&file_name).unwrap()[..10].to_vec();
+        let ts_codes = vec!["689009.SH".to_owned(), "688981.SH".to_owned()];
+        let trade_dates = crawl_trade_cal(source.as_ref(), start_date, end_date)
+            .unwrap()
+            .2;
+        assert_eq!(trade_dates.len(), 8);
+        let plan = DownloadPlan::new(&ts_codes, &trade_dates, MAX_ROWS_PER_CALL);
+
+        let mut checkpoint = Checkpoint::open(
+            &date_dir,
+            CheckpointHeader {
+                start_date: start_date.to_owned(),
+                end_date: end_date.to_owned(),
+                append: false,
+            },
+        )
+        .unwrap();
+
+        let datasets = "daily,daily_basic,adj_factor"
+            .parse::<DownloadType>()
+            .unwrap()
+            .datasets();
+        download_stocks_daily(
+            &date_dir,
+            &source,
+            &plan,
+            &HashMap::new(),
+            &datasets,
+            &mut checkpoint,
+            2,
+        )
+        .unwrap();
+        finish_download(&date_dir, &datasets, false).unwrap();
+        checkpoint.remove().unwrap();
+
+        assert!(date_dir.join("_SUCCESS").exists());
+        for dir in &["daily_data", "daily_basic_data", "adj_factor_data"] {
+            for ts_code in &ts_codes {
+                let content = fs::read_to_string(date_dir.join(dir).join(ts_code)).unwrap();
+                // header and 8 trade days
+                assert_eq!(content.lines().count(), 9);
+            }
+        }
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_download_calls() {
+        let ts_codes = vec!["689009.SH".to_owned(), "688981.SH".to_owned()];
+        let trade_dates: Vec<String> = (1..=8).map(|d| format!("202109{:02}", d)).collect();
+        let plan = DownloadPlan::new(&ts_codes, &trade_dates, MAX_ROWS_PER_CALL);
+        let datasets = "daily,daily_basic,index_daily,dividend,income"
+            .parse::<DownloadType>()
+            .unwrap()
+            .datasets();
+        let index_codes = vec!["000001.SH".to_owned()];
+        let type_plans = type_plans(
+            &plan,
+            &datasets,
+            [&ts_codes, &index_codes, &[]],
+            &trade_dates,
+            ["20210901", "20210908"],
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            type_plans["income"].windows[0].start_date,
+            financial_start_date("20210901").unwrap()
+        );
+        // one call for each stock groups dataset, one for the index and one a stock for the others
+        assert_eq!(
+            download_calls(&plan, &type_plans, &datasets),
+            1 + 1 + 1 + 2 + 2
+        );
+    }
+
+    // daily of 000002.SZ panics
+    struct PanicSource;
+    impl DataSource for PanicSource {
+        fn name(&self) -> String {
+            String::from("panic")
+        }
+        fn stocks_basic(
+            &self,
+            _market: &Market,
+            _list_status: ListStatus,
+        ) -> Result<Vec<StockBasic>, Box<dyn Error>> {
+            Ok(vec![])
+        }
+        fn trade_calendar(
+            &self,
+            _exchange: &str,
+            _start_date: &str,
+            _end_date: &str,
+        ) -> Result<TradingCalendar, Box<dyn Error>> {
+            Err(Box::new(MyError(String::from("no calendar"))))
+        }
+        fn dataset_rows(
+            &self,
+            _dataset: &Dataset,
+            ts_codes: &[String],
+            _start_date: &str,
+            _end_date: &str,
+        ) -> Result<DatasetRows, Box<dyn Error>> {
+            if ts_codes.contains(&"000002.SZ".to_owned()) {
+                panic!("bad group");
+            }
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn test_download_groups_concurrently_panic() {
+        let source: Arc<dyn DataSource> = Arc::new(PanicSource);
+        let tasks: Vec<GroupTask> = ["000001.SZ", "000002.SZ", "000004.SZ"]
+            .iter()
+            .map(|ts_code| group_task("daily", &[ts_code]))
+            .collect();
+        let mut handled = 0;
+        let result = download_groups_concurrently(&source, tasks, 2, &mut |_, _| {
+            handled += 1;
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert!(handled < 3);
+    }
+
+    #[test]
+    fn test_find_latest_date_dir() {
+        let data_dir = std::env::temp_dir().join("choose_some_test_find_latest_date_dir");
+        if data_dir.exists() {
+            fs::remove_dir_all(&data_dir).unwrap();
+        }
+        for (date, finished) in [("20210910", true), ("20210917", true), ("20210924", false)] {
+            let date_dir = data_dir.join(date);
+            fs::create_dir_all(&date_dir).unwrap();
+            if finished {
+                fs::File::create(date_dir.join("_SUCCESS")).unwrap();
+            }
+        }
+        fs::create_dir_all(data_dir.join("tmp")).unwrap();
 
         assert_eq!(
-            download_stocks_daily(
-                &date_dir,
-                &token,
-                stocks_basic,
+            find_latest_date_dir(&data_dir).unwrap(),
+            Some(data_dir.join("20210917"))
+        );
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_update_interrupted_before_rename() {
+        let data_dir = std::env::temp_dir().join("choose_some_test_update_interrupted");
+        if data_dir.exists() {
+            fs::remove_dir_all(&data_dir).unwrap();
+        }
+        let from_date_dir = data_dir.join("20210831");
+        fs::create_dir_all(from_date_dir.join("daily_data")).unwrap();
+        fs::write(
+            from_date_dir.join("daily_data").join("000001.SZ"),
+            "history",
+        )
+        .unwrap();
+        let header = CheckpointHeader {
+            start_date: "20210901".to_owned(),
+            end_date: "20210910".to_owned(),
+            append: true,
+        };
+        // stopped after the checkpoint and _SUCCESS steps of start_update, before the rename
+        Checkpoint::open(&from_date_dir, header.clone()).unwrap();
+
+        let date_dir = data_dir.join("20210910");
+        match prepare_update(&replay_client(), &data_dir, "20210910").unwrap() {
+            UpdatePlan::Missing(_, trade_dates, new_date_dir, update_from_dir) => {
+                assert_eq!(trade_dates.len(), 8);
+                assert_eq!(new_date_dir, date_dir);
+                assert_eq!(update_from_dir, Some(from_date_dir.clone()));
+            }
+            UpdatePlan::UpToDate(_) => panic!("the update is not finished"),
+        }
+
+        start_update(&from_date_dir, &date_dir, &header).unwrap();
+        assert!(!from_date_dir.exists());
+        assert_eq!(Checkpoint::read_header(&date_dir), Some(header));
+        assert_eq!(
+            fs::read_to_string(date_dir.join("daily_data").join("000001.SZ")).unwrap(),
+            "history"
+        );
+        assert_eq!(
+            find_unfinished_update(&data_dir).unwrap().map(|(d, _)| d),
+            Some(date_dir)
+        );
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_listed_in_range() {
+        let delisted = StockBasic::from_string(String::from(
+            "000003.SZ\t000003\tPT金田A\t\t\t\t\t\t主板\tSZSE\tCNY\tD\t19910703\t20020614\tN",
+        ));
+        assert_eq!(delisted.delist_date, Some("20020614".to_owned()));
+        let delisted_in_range = |start_date: &str, end_date: &str| {
+            listed_in_range(
+                &delisted.list_date,
+                delisted.delist_date.as_deref(),
                 start_date,
                 end_date,
-                DownloadType::All,
             )
-            .unwrap(),
-            ()
+        };
+        assert!(delisted_in_range("20020101", "20021231"));
+        assert!(!delisted_in_range("20020617", "20021231"));
+        assert!(!delisted_in_range("19900101", "19910630"));
+
+        let listed = StockBasic::from_string(String::from(
+            "000001.SZ\t000001\t平安银行\t深圳\t银行\t\t\t\t主板\tSZSE\tCNY\tL\t19910403\tnone\tS",
+        ));
+        assert!(listed_in_range(
+            &listed.list_date,
+            listed.delist_date.as_deref(),
+            "20210101",
+            "20210901"
+        ));
+    }
+
+    #[test]
+    fn test_financial_start_date() {
+        assert_eq!(financial_start_date("20210104").unwrap(), "20190101");
+        assert_eq!(financial_start_date("20200915").unwrap(), "20180901");
+    }
+
+    #[test]
+    fn test_merge_data_file() {
+        let data_dir = std::env::temp_dir().join("choose_some_test_merge_data_file");
+        fs::create_dir_all(&data_dir).unwrap();
+        let file_name = data_dir.join("000001.SZ");
+        let header = "ts_code\ttrade_date";
+        merge_data_file(
+            &file_name,
+            header,
+            vec![
+                "000001.SZ\t20210916".to_owned(),
+                "000001.SZ\t20210915".to_owned(),
+            ],
+            1,
+            "20210915",
+            "20210916",
+        )
+        .unwrap();
+        // an interrupted write only leaves its temp file
+        let tmp_file_name = data_dir.join(".000001.SZ.tmp");
+        fs::write(&tmp_file_name, "ts_code\ttrade_").unwrap();
+        // a resumed update of 20210916 - 20210917 replaces the half written rows
+        merge_data_file(
+            &file_name,
+            header,
+            vec![
+                "000001.SZ\t20210917".to_owned(),
+                "000001.SZ\t20210916".to_owned(),
+            ],
+            1,
+            "20210916",
+            "20210917",
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&file_name).unwrap(),
+            "ts_code\ttrade_date\n000001.SZ\t20210915\n000001.SZ\t20210916\n000001.SZ\t20210917\n"
         );
+        assert!(!tmp_file_name.exists());
+        fs::remove_dir_all(&data_dir).unwrap();
     }
 }
@@ -0,0 +1,674 @@
+/// Registry of downloadable datasets
+/// a dataset is declared once in DATASETS: its tushare api, the fields stored, key columns,
+/// how calls and files are partitioned, which fields are text and which may be null.
+/// download, storage and loading all work from the declaration.
+/// files are <dir>/<ts_code> of a per stock dataset and <dir>/<trade_date> of a per date one,
+/// one row a record in the order of fields, a null is stored as "none".
+/// a stored record decodes into a model by field names like a tushare row, see Record::decode.
+use crate::financial::STATEMENTS;
+use serde::de::value::{Error as ValueError, MapDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// how a dataset is split into calls and files
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Partition {
+    /// one file a code
+    PerStock(Calls),
+    /// one file a trade date, one call a trade date with all stocks
+    PerDate,
+}
+
+/// calls of a per stock dataset
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Calls {
+    /// stocks in groups and date windows of the download plan, codes joined by comma
+    StockGroups,
+    /// one code a call in date windows of the download plan
+    OneCode(Codes),
+    /// one stock a call from the financial lookback date, reports known at the start date
+    Lookback,
+    /// one stock a call without date params, the api returns all history
+    History,
+}
+
+/// what the codes of a per stock dataset are
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Codes {
+    Stocks,
+    Indexes,
+    Funds,
+}
+
+pub struct Dataset {
+    /// download type name, also the name in checkpoint and _SUCCESS
+    pub name: &'static str,
+    /// download type selecting it with others of the group, like fund for fund_daily and fund_adj
+    pub group: &'static str,
+    pub api_name: &'static str,
+    pub dir: &'static str,
+    /// stored fields in file column order
+    pub fields: &'static [&'static str],
+    /// a record is unique by them, the last one of the same key is kept
+    pub key_columns: &'static [&'static str],
+    /// rows are windowed and sorted by it
+    pub date_field: &'static str,
+    /// text fields, the others are numbers, a row with a field of the wrong type is dropped
+    pub text: &'static [&'static str],
+    /// fields may be null, a row with another field null is dropped
+    pub nullable: &'static [&'static str],
+    /// asked but not stored fields a row must have the value of, others are dropped
+    pub only: &'static [(&'static str, &'static str)],
+    pub partition: Partition,
+    /// also served by a data source without tushare
+    pub from_source: bool,
+    /// selected by download type all, others need higher tushare points and are asked by name
+    pub in_all: bool,
+}
+
+const DAILY_FIELDS: &[&str] = &[
+    "ts_code",
+    "trade_date",
+    "open",
+    "high",
+    "low",
+    "close",
+    "pre_close",
+    "change",
+    "pct_chg",
+    "vol",
+    "amount",
+];
+// some indexes have no volume in early years
+const DAILY_NULLABLE: &[&str] = &["vol", "amount"];
+const ADJ_FACTOR_FIELDS: &[&str] = &["ts_code", "trade_date", "adj_factor"];
+const TRADE_KEY: &[&str] = &["ts_code", "trade_date"];
+const FINANCIAL_TEXT: &[&str] = &[
+    "ts_code",
+    "ann_date",
+    "end_date",
+    "f_ann_date",
+    "report_type",
+    "comp_type",
+    "update_flag",
+];
+const FINANCIAL_KEY: &[&str] = &["ts_code", "ann_date", "end_date", "update_flag"];
+
+// a financial statement, values after ts_code, ann_date and end_date may be null
+const fn financial(index: usize) -> Dataset {
+    Dataset {
+        name: STATEMENTS[index].api_name,
+        group: "financial",
+        api_name: STATEMENTS[index].api_name,
+        dir: STATEMENTS[index].dir,
+        fields: STATEMENTS[index].fields,
+        key_columns: FINANCIAL_KEY,
+        date_field: "ann_date",
+        text: FINANCIAL_TEXT,
+        nullable: match STATEMENTS[index].fields {
+            [_, _, _, values @ ..] => values,
+            _ => &[],
+        },
+        only: &[],
+        partition: Partition::PerStock(Calls::Lookback),
+        from_source: false,
+        in_all: false,
+    }
+}
+
+pub const DATASETS: [Dataset; 14] = [
+    Dataset {
+        name: "daily",
+        group: "daily",
+        api_name: "daily",
+        dir: "daily_data",
+        fields: DAILY_FIELDS,
+        key_columns: TRADE_KEY,
+        date_field: "trade_date",
+        text: TRADE_KEY,
+        nullable: DAILY_NULLABLE,
+        only: &[],
+        partition: Partition::PerStock(Calls::StockGroups),
+        from_source: true,
+        in_all: true,
+    },
+    Dataset {
+        name: "daily_basic",
+        group: "daily_basic",
+        api_name: "daily_basic",
+        dir: "daily_basic_data",
+        fields: &[
+            "ts_code",
+            "trade_date",
+            "close",
+            "turnover_rate",
+            "turnover_rate_f",
+            "volume_ratio",
+            "pe",
+            "pe_ttm",
+            "pb",
+            "ps",
+            "ps_ttm",
+            "dv_ratio",
+            "dv_ttm",
+            "total_share",
+            "float_share",
+            "free_share",
+            "total_mv",
+            "circ_mv",
+            "limit_status",
+        ],
+        key_columns: TRADE_KEY,
+        date_field: "trade_date",
+        text: TRADE_KEY,
+        nullable: &[
+            "turnover_rate_f",
+            "volume_ratio",
+            "pe",
+            "pe_ttm",
+            "pb",
+            "ps",
+            "ps_ttm",
+            "dv_ratio",
+            "dv_ttm",
+            "limit_status",
+        ],
+        only: &[],
+        partition: Partition::PerStock(Calls::StockGroups),
+        from_source: true,
+        in_all: true,
+    },
+    Dataset {
+        name: "adj_factor",
+        group: "adj_factor",
+        api_name: "adj_factor",
+        dir: "adj_factor_data",
+        fields: ADJ_FACTOR_FIELDS,
+        key_columns: TRADE_KEY,
+        date_field: "trade_date",
+        text: TRADE_KEY,
+        nullable: &[],
+        only: &[],
+        partition: Partition::PerStock(Calls::StockGroups),
+        from_source: false,
+        in_all: false,
+    },
+    Dataset {
+        name: "index_daily",
+        group: "index",
+        api_name: "index_daily",
+        dir: "index_data",
+        fields: DAILY_FIELDS,
+        key_columns: TRADE_KEY,
+        date_field: "trade_date",
+        text: TRADE_KEY,
+        nullable: DAILY_NULLABLE,
+        only: &[],
+        partition: Partition::PerStock(Calls::OneCode(Codes::Indexes)),
+        from_source: false,
+        in_all: false,
+    },
+    Dataset {
+        name: "fund_daily",
+        group: "fund",
+        api_name: "fund_daily",
+        dir: "fund_data",
+        fields: DAILY_FIELDS,
+        key_columns: TRADE_KEY,
+        date_field: "trade_date",
+        text: TRADE_KEY,
+        nullable: DAILY_NULLABLE,
+        only: &[],
+        partition: Partition::PerStock(Calls::OneCode(Codes::Funds)),
+        from_source: false,
+        in_all: false,
+    },
+    Dataset {
+        name: "fund_adj",
+        group: "fund",
+        api_name: "fund_adj",
+        dir: "fund_adj_data",
+        fields: ADJ_FACTOR_FIELDS,
+        key_columns: TRADE_KEY,
+        date_field: "trade_date",
+        text: TRADE_KEY,
+        nullable: &[],
+        only: &[],
+        partition: Partition::PerStock(Calls::OneCode(Codes::Funds)),
+        from_source: false,
+        in_all: false,
+    },
+    financial(0),
+    financial(1),
+    financial(2),
+    financial(3),
+    // implemented dividends with ex_date in the range
+    Dataset {
+        name: "dividend",
+        group: "dividend",
+        api_name: "dividend",
+        dir: "dividend_data",
+        fields: &[
+            "ts_code",
+            "ex_date",
+            "end_date",
+            "ann_date",
+            "record_date",
+            "pay_date",
+            "stk_div",
+            "cash_div_tax",
+        ],
+        key_columns: &["ts_code", "ex_date", "end_date"],
+        date_field: "ex_date",
+        text: &[
+            "ts_code",
+            "ex_date",
+            "end_date",
+            "ann_date",
+            "record_date",
+            "pay_date",
+        ],
+        nullable: &[
+            "end_date",
+            "ann_date",
+            "record_date",
+            "pay_date",
+            "stk_div",
+            "cash_div_tax",
+        ],
+        only: &[("div_proc", "实施")],
+        partition: Partition::PerStock(Calls::History),
+        from_source: false,
+        in_all: false,
+    },
+    Dataset {
+        name: "suspend_d",
+        group: "suspend",
+        api_name: "suspend_d",
+        dir: "suspend_data",
+        fields: &["ts_code", "trade_date", "suspend_timing", "suspend_type"],
+        key_columns: TRADE_KEY,
+        date_field: "trade_date",
+        text: &["ts_code", "trade_date", "suspend_timing", "suspend_type"],
+        nullable: &["suspend_timing"],
+        only: &[],
+        partition: Partition::PerStock(Calls::OneCode(Codes::Stocks)),
+        from_source: false,
+        in_all: false,
+    },
+    // no limits on the first days of a new listing, those rows are dropped
+    Dataset {
+        name: "stk_limit",
+        group: "stk_limit",
+        api_name: "stk_limit",
+        dir: "stk_limit_data",
+        fields: &["ts_code", "trade_date", "up_limit", "down_limit"],
+        key_columns: TRADE_KEY,
+        date_field: "trade_date",
+        text: TRADE_KEY,
+        nullable: &[],
+        only: &[],
+        partition: Partition::PerStock(Calls::OneCode(Codes::Stocks)),
+        from_source: false,
+        in_all: false,
+    },
+    Dataset {
+        name: "moneyflow",
+        group: "moneyflow",
+        api_name: "moneyflow",
+        dir: "moneyflow_data",
+        fields: &[
+            "ts_code",
+            "trade_date",
+            "buy_sm_vol",
+            "buy_sm_amount",
+            "sell_sm_vol",
+            "sell_sm_amount",
+            "buy_md_vol",
+            "buy_md_amount",
+            "sell_md_vol",
+            "sell_md_amount",
+            "buy_lg_vol",
+            "buy_lg_amount",
+            "sell_lg_vol",
+            "sell_lg_amount",
+            "buy_elg_vol",
+            "buy_elg_amount",
+            "sell_elg_vol",
+            "sell_elg_amount",
+            "net_mf_vol",
+            "net_mf_amount",
+        ],
+        key_columns: TRADE_KEY,
+        date_field: "trade_date",
+        text: TRADE_KEY,
+        nullable: &[
+            "buy_sm_vol",
+            "buy_sm_amount",
+            "sell_sm_vol",
+            "sell_sm_amount",
+            "buy_md_vol",
+            "buy_md_amount",
+            "sell_md_vol",
+            "sell_md_amount",
+            "buy_lg_vol",
+            "buy_lg_amount",
+            "sell_lg_vol",
+            "sell_lg_amount",
+            "buy_elg_vol",
+            "buy_elg_amount",
+            "sell_elg_vol",
+            "sell_elg_amount",
+            "net_mf_vol",
+            "net_mf_amount",
+        ],
+        only: &[],
+        partition: Partition::PerStock(Calls::OneCode(Codes::Stocks)),
+        from_source: false,
+        in_all: false,
+    },
+];
+
+pub fn dataset(name: &str) -> Option<&'static Dataset> {
+    DATASETS.iter().find(|d| d.name == name)
+}
+
+/// datasets of a download type name, a dataset name or a group
+pub fn named(name: &str) -> Vec<&'static Dataset> {
+    DATASETS
+        .iter()
+        .filter(|d| d.name == name || d.group == name)
+        .collect()
+}
+
+impl Dataset {
+    /// fields param of the api, only fields are asked too
+    pub fn fields_param(&self) -> String {
+        let mut fields: Vec<&str> = self.fields.to_vec();
+        fields.extend(self.only.iter().map(|(field, _)| *field));
+        fields.join(",")
+    }
+
+    pub fn header(&self) -> String {
+        self.fields.join("\t")
+    }
+
+    /// column of a stored field
+    pub fn column(&self, field: &str) -> Option<usize> {
+        self.fields.iter().position(|f| *f == field)
+    }
+
+    /// one stored row of a tushare item, fields are the response fields in item order.
+    /// None if the row is not the only value, a null field not nullable or a number field
+    /// that is not a number is an error
+    pub fn row(
+        &self,
+        fields: &[String],
+        item: &[serde_json::Value],
+    ) -> Result<Option<Vec<String>>, String> {
+        let values: HashMap<&str, &serde_json::Value> =
+            fields.iter().map(|f| f.as_str()).zip(item.iter()).collect();
+        let text = |field: &str| match values.get(field) {
+            None | Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::String(s)) if s.is_empty() => None,
+            Some(serde_json::Value::String(s)) => Some(s.to_owned()),
+            Some(v) => Some(v.to_string()),
+        };
+        for (field, value) in self.only {
+            if text(field).as_deref() != Some(*value) {
+                return Ok(None);
+            }
+        }
+        let mut row: Vec<String> = vec![];
+        for field in self.fields {
+            match text(field) {
+                Some(value) if !self.text.contains(field) && value.parse::<f64>().is_err() => {
+                    return Err(format!(
+                        "{} row {} is not a number: {:?}",
+                        self.name, field, item
+                    ))
+                }
+                Some(value) => row.push(value),
+                None if self.nullable.contains(field) => row.push("none".to_owned()),
+                None => return Err(format!("{} row has no {}: {:?}", self.name, field, item)),
+            }
+        }
+        Ok(Some(row))
+    }
+
+    /// key column values of a stored row
+    pub fn key<'a>(&self, row: &'a [String]) -> Vec<&'a str> {
+        self.key_columns
+            .iter()
+            .filter_map(|k| self.column(k))
+            .map(|i| row.get(i).map(|v| v.as_str()).unwrap_or_default())
+            .collect()
+    }
+}
+
+/// one stored record, values are by field name, "none" if tushare has no value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub values: HashMap<String, String>,
+}
+
+impl Record {
+    pub fn text(&self, field: &str) -> Option<&str> {
+        self.values
+            .get(field)
+            .map(|v| v.as_str())
+            .filter(|v| *v != "none")
+    }
+
+    pub fn value(&self, field: &str) -> Option<f64> {
+        self.text(field)?.parse::<f64>().ok()
+    }
+
+    /// the record as T by field names, fields T has no field for are ignored,
+    /// "none" or a missing field is None of an Option field
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, String> {
+        let cells = self.values.iter().map(|(f, v)| (f.as_str(), Cell(v)));
+        T::deserialize(MapDeserializer::<_, ValueError>::new(cells))
+            .map_err(|e| format!("{}, record: {:?}", e, self.values))
+    }
+}
+
+// one stored value, parsed as the type asked, "none" is null
+struct Cell<'a>(&'a str);
+
+impl<'a> Cell<'a> {
+    fn parse<T: std::str::FromStr>(&self) -> Result<T, ValueError>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.0
+            .parse::<T>()
+            .map_err(|e| de::Error::custom(format!("{}: {}", self.0, e)))
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Cell<'a> {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_str(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        if self.0 == "none" {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_bool(self.parse()?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_i64(self.parse()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_u64(self.parse()?)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_f64(self.parse()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        self.deserialize_f64(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i128 u8 u16 u32 u128 char str string bytes byte_buf unit unit_struct
+        newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, ValueError> for Cell<'a> {
+    type Deserializer = Cell<'a>;
+
+    fn into_deserializer(self) -> Cell<'a> {
+        self
+    }
+}
+
+/// a file of a dataset is downloaded
+pub fn has_file(date_dir: &Path, name: &str, file_key: &str) -> bool {
+    dataset(name).is_some_and(|d| date_dir.join(d.dir).join(file_key).exists())
+}
+
+/// all records of one file of a dataset, a ts_code of a per stock dataset
+/// or a trade_date of a per date one
+pub fn read_records(
+    date_dir: &Path,
+    name: &str,
+    file_key: &str,
+) -> Result<Vec<Record>, Box<dyn Error>> {
+    let dataset = match dataset(name) {
+        Some(dataset) => dataset,
+        None => return Err(format!("unknown dataset: {}", name).into()),
+    };
+    read_file(&date_dir.join(dataset.dir).join(file_key))
+}
+
+/// all records of one file of a dataset as T
+pub fn read_as<T: DeserializeOwned>(
+    date_dir: &Path,
+    name: &str,
+    file_key: &str,
+) -> Result<Vec<T>, Box<dyn Error>> {
+    let mut rows: Vec<T> = vec![];
+    for record in read_records(date_dir, name, file_key)? {
+        rows.push(record.decode()?);
+    }
+    Ok(rows)
+}
+
+/// records of any tab separated file with a header, like a dataset file, index_list or funds_list
+pub fn read_file(file_name: &Path) -> Result<Vec<Record>, Box<dyn Error>> {
+    let content =
+        fs::read_to_string(file_name).map_err(|e| format!("read {:?} error: {}", file_name, e))?;
+    let mut lines = content.lines();
+    // fields of the file header, older files may have fewer
+    let fields: Vec<&str> = lines.next().unwrap_or_default().split('\t').collect();
+    Ok(lines
+        .filter(|l| !l.is_empty())
+        .map(|line| Record {
+            values: fields
+                .iter()
+                .zip(line.split('\t'))
+                .map(|(f, v)| (f.to_string(), v.to_owned()))
+                .collect(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        for (i, dataset) in DATASETS.iter().enumerate() {
+            assert!(DATASETS[..i].iter().all(|d| d.name != dataset.name));
+            assert!(dataset.column(dataset.date_field).is_some());
+            assert!(dataset
+                .key_columns
+                .iter()
+                .all(|k| dataset.column(k).is_some()));
+        }
+        assert_eq!(named("fund").len(), 2);
+        assert_eq!(named("financial").len(), 4);
+        assert_eq!(named("suspend_d")[0].dir, "suspend_data");
+        assert!(named("minute").is_empty());
+        assert_eq!(
+            dataset("income").unwrap().fields_param(),
+            STATEMENTS[0].fields.join(",")
+        );
+    }
+
+    #[test]
+    fn test_row_by_fields() {
+        let dividend = dataset("dividend").unwrap();
+        let fields: Vec<String> = "ex_date,ts_code,div_proc,stk_div,cash_div_tax,end_date"
+            .split(',')
+            .map(|f| f.to_owned())
+            .collect();
+        let item = serde_json::json!(["20210601", "000001.SZ", "实施", null, 0.2, "20201231"]);
+        assert_eq!(
+            dividend.row(&fields, item.as_array().unwrap()).unwrap(),
+            Some(
+                vec![
+                    "000001.SZ",
+                    "20210601",
+                    "20201231",
+                    "none",
+                    "none",
+                    "none",
+                    "none",
+                    "0.2"
+                ]
+                .into_iter()
+                .map(|v| v.to_owned())
+                .collect()
+            )
+        );
+        let item = serde_json::json!(["20210601", "000001.SZ", "预案", null, 0.2, "20201231"]);
+        assert_eq!(dividend.row(&fields, item.as_array().unwrap()), Ok(None));
+        let item = serde_json::json!([null, "000001.SZ", "实施", null, 0.2, "20201231"]);
+        assert!(dividend.row(&fields, item.as_array().unwrap()).is_err());
+        // a number of a response from a csv source is text
+        let item = serde_json::json!(["20210601", "000001.SZ", "实施", null, "0.2", "20201231"]);
+        assert!(dividend.row(&fields, item.as_array().unwrap()).is_ok());
+        let item = serde_json::json!(["20210601", "000001.SZ", "实施", null, "x", "20201231"]);
+        assert!(dividend.row(&fields, item.as_array().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_read_records() {
+        let date_dir = std::env::temp_dir().join("choose_some_test_read_records");
+        fs::create_dir_all(date_dir.join("stk_limit_data")).unwrap();
+        fs::write(
+            date_dir.join("stk_limit_data").join("000001.SZ"),
+            "ts_code\ttrade_date\tup_limit\tdown_limit\n000001.SZ\t20210901\t20.35\tnone\n",
+        )
+        .unwrap();
+
+        let records = read_records(&date_dir, "stk_limit", "000001.SZ").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].text("trade_date"), Some("20210901"));
+        assert_eq!(records[0].value("up_limit"), Some(20.35));
+        assert_eq!(records[0].text("down_limit"), None);
+        assert!(read_records(&date_dir, "minute", "000001.SZ").is_err());
+
+        fs::remove_dir_all(&date_dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+/// One-off pull of any tushare api, like namechange or hs_const
+/// `fetch --api namechange --param ts_code=600000.SH --fields ts_code,name,start_date --out namechange`
+/// goes through the download client, so pagination, rate limit, retries and cassettes apply.
+/// the result is tab separated with a header of the response fields like stocks_list,
+/// a null is "none".
+use crate::tushare::{TushareClient, TushareData};
+use crate::Config;
+use log::info;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+/// fetch subcommand, fields empty are the api default fields
+#[derive(Debug, PartialEq, Clone)]
+pub struct FetchRequest {
+    pub api_name: String,
+    pub params: Vec<(String, String)>,
+    pub fields: String,
+    // stdout if none
+    pub out: Option<String>,
+}
+
+/// one --param k=v, the value may be empty
+pub fn parse_param(a_str: &str) -> Result<(String, String), String> {
+    let mut kv = a_str.splitn(2, '=');
+    let key = kv.next().unwrap_or_default().trim();
+    match kv.next() {
+        Some(value) if !key.is_empty() => Ok((key.to_owned(), value.trim().to_owned())),
+        _ => Err(format!("param should be k=v: {}", a_str)),
+    }
+}
+
+pub fn run(config: &Config, request: &FetchRequest) -> Result<(), Box<dyn Error>> {
+    let client = TushareClient::from_config(config)?;
+    let params: HashMap<String, String> = request.params.iter().cloned().collect();
+    let data = client.query(&request.api_name, params, &request.fields)?;
+    let content = to_tsv(&data);
+    match &request.out {
+        Some(out) => {
+            fs::write(out, content).map_err(|e| format!("write {} error: {}", out, e))?;
+            info!(
+                "{} rows of {} into {}",
+                data.items.len(),
+                request.api_name,
+                out
+            );
+        }
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+// tabs and line breaks in a value would break the row, they become spaces
+fn to_tsv(data: &TushareData) -> String {
+    let cell = |value: &serde_json::Value| match value {
+        serde_json::Value::Null => "none".to_owned(),
+        serde_json::Value::String(s) => s.replace(['\t', '\r', '\n'], " "),
+        v => v.to_string(),
+    };
+    let mut content = data.fields.join("\t");
+    content.push('\n');
+    for item in &data.items {
+        let row: Vec<String> = item.iter().map(cell).collect();
+        content.push_str(&row.join("\t"));
+        content.push('\n');
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tushare::decode_response;
+
+    #[test]
+    fn test_fetch_tsv() {
+        assert_eq!(
+            parse_param("ts_code= 600000.SH").unwrap(),
+            ("ts_code".to_owned(), "600000.SH".to_owned())
+        );
+        assert_eq!(
+            parse_param("is_new=").unwrap(),
+            ("is_new".to_owned(), "".to_owned())
+        );
+        assert!(parse_param("ts_code").is_err());
+        assert!(parse_param("=1").is_err());
+
+        let body = r#"{"request_id":"1","code":0,"msg":"","data":{"fields":["ts_code","name","start_date","end_date","change_reason"],"items":[["600000.SH","浦发银行","19991110",null,"改名\t更名"],["600000.SH","浦发银行","20060101",20061231,"其他"]],"has_more":false}}"#;
+        let (data, _) = decode_response("namechange", body).unwrap();
+        assert_eq!(
+            to_tsv(&data),
+            "ts_code\tname\tstart_date\tend_date\tchange_reason\n\
+             600000.SH\t浦发银行\t19991110\tnone\t改名 更名\n\
+             600000.SH\t浦发银行\t20060101\t20061231\t其他\n"
+        );
+    }
+}
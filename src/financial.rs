@@ -0,0 +1,285 @@
+/// Point-in-time financial statements
+/// income, balancesheet, cashflow and fina_indicator of every stock are stored in
+/// <api_name>_data/<ts_code>, one row a report: ts_code, ann_date, end_date, then other fields.
+/// rows are sorted by ann_date (公告日期), end_date is the report period.
+/// a backtest on trade date X may only see reports announced before X,
+/// announcements come out after the close, so one announced on X is seen from the next day.
+/// files are written and read by the dataset registry, see dataset.rs.
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use crate::dataset::{self, Record};
+
+/// one financial api and the fields stored, ts_code, ann_date and end_date come first
+pub struct Statement {
+    pub api_name: &'static str,
+    pub dir: &'static str,
+    pub fields: &'static [&'static str],
+}
+
+pub const STATEMENTS: [Statement; 4] = [
+    Statement {
+        api_name: "income",
+        dir: "income_data",
+        fields: &[
+            "ts_code",
+            "ann_date",
+            "end_date",
+            "f_ann_date",
+            "report_type",
+            "comp_type",
+            "basic_eps",
+            "diluted_eps",
+            "total_revenue",
+            "revenue",
+            "operate_profit",
+            "total_profit",
+            "income_tax",
+            "n_income",
+            "n_income_attr_p",
+            "ebit",
+            "ebitda",
+            "update_flag",
+        ],
+    },
+    Statement {
+        api_name: "balancesheet",
+        dir: "balancesheet_data",
+        fields: &[
+            "ts_code",
+            "ann_date",
+            "end_date",
+            "f_ann_date",
+            "report_type",
+            "comp_type",
+            "total_share",
+            "cap_rese",
+            "undistr_porfit",
+            "money_cap",
+            "accounts_receiv",
+            "inventories",
+            "total_cur_assets",
+            "total_assets",
+            "total_cur_liab",
+            "total_liab",
+            "total_hldr_eqy_exc_min_int",
+            "total_liab_hldr_eqy",
+            "update_flag",
+        ],
+    },
+    Statement {
+        api_name: "cashflow",
+        dir: "cashflow_data",
+        fields: &[
+            "ts_code",
+            "ann_date",
+            "end_date",
+            "f_ann_date",
+            "report_type",
+            "comp_type",
+            "n_cashflow_act",
+            "n_cashflow_inv_act",
+            "n_cash_flows_fnc_act",
+            "c_cash_equ_end_period",
+            "free_cashflow",
+            "update_flag",
+        ],
+    },
+    Statement {
+        api_name: "fina_indicator",
+        dir: "fina_indicator_data",
+        fields: &[
+            "ts_code",
+            "ann_date",
+            "end_date",
+            "eps",
+            "dt_eps",
+            "bps",
+            "ocfps",
+            "roe",
+            "roe_dt",
+            "roa",
+            "grossprofit_margin",
+            "netprofit_margin",
+            "debt_to_assets",
+            "current_ratio",
+            "quick_ratio",
+            "or_yoy",
+            "netprofit_yoy",
+            "update_flag",
+        ],
+    },
+];
+
+pub fn statement(api_name: &str) -> Option<&'static Statement> {
+    STATEMENTS.iter().find(|s| s.api_name == api_name)
+}
+
+/// one report of one stock, values are by field name, "none" if tushare has no value
+#[derive(Debug, Clone, PartialEq)]
+pub struct FinancialRecord {
+    pub ts_code: String,
+    pub ann_date: String,
+    pub end_date: String,
+    pub values: HashMap<String, String>,
+}
+
+impl FinancialRecord {
+    /// one stored record of the statement, ts_code, ann_date and end_date are required
+    pub fn from_record(api_name: &str, record: Record) -> Result<FinancialRecord, String> {
+        let values = record.values;
+        let key = |field: &str| match values.get(field) {
+            Some(value) if value != "none" && !value.is_empty() => Ok(value.to_owned()),
+            _ => Err(format!(
+                "{} record has no {}: {:?}",
+                api_name, field, values
+            )),
+        };
+        Ok(FinancialRecord {
+            ts_code: key("ts_code")?,
+            ann_date: key("ann_date")?,
+            end_date: key("end_date")?,
+            values,
+        })
+    }
+
+    pub fn value(&self, field: &str) -> Option<f64> {
+        self.values.get(field)?.parse::<f64>().ok()
+    }
+}
+
+/// all reports of one stock in date dir, sorted by ann_date
+pub fn read_statement(
+    date_dir: &Path,
+    api_name: &str,
+    ts_code: &str,
+) -> Result<Vec<FinancialRecord>, Box<dyn Error>> {
+    if statement(api_name).is_none() {
+        return Err(format!("unknown financial statement: {}", api_name).into());
+    }
+    let mut records: Vec<FinancialRecord> = vec![];
+    for record in dataset::read_records(date_dir, api_name, ts_code)? {
+        records.push(FinancialRecord::from_record(api_name, record)?);
+    }
+    Ok(records)
+}
+
+/// reports known before trade_date, the latest announced version of every report period,
+/// sorted by end_date
+pub fn as_of(records: &[FinancialRecord], trade_date: &str) -> Vec<FinancialRecord> {
+    let mut known: Vec<&FinancialRecord> = records
+        .iter()
+        .filter(|r| r.ann_date.as_str() < trade_date)
+        .collect();
+    // a restated report has a later ann_date, update_flag 1 is the updated one of the same day
+    known.sort_by(|a, b| {
+        (&a.end_date, &a.ann_date, a.values.get("update_flag")).cmp(&(
+            &b.end_date,
+            &b.ann_date,
+            b.values.get("update_flag"),
+        ))
+    });
+    let mut latest: Vec<FinancialRecord> = vec![];
+    for record in known {
+        match latest.last_mut() {
+            Some(last) if last.end_date == record.end_date => *last = record.clone(),
+            _ => latest.push(record.clone()),
+        }
+    }
+    latest
+}
+
+/// what one stock's statement was known as of trade date, the latest report period is last
+pub fn load_as_of(
+    date_dir: &Path,
+    api_name: &str,
+    ts_code: &str,
+    trade_date: &str,
+) -> Result<Vec<FinancialRecord>, Box<dyn Error>> {
+    Ok(as_of(
+        &read_statement(date_dir, api_name, ts_code)?,
+        trade_date,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn record(
+        ann_date: &str,
+        end_date: &str,
+        update_flag: &str,
+        n_income: &str,
+    ) -> FinancialRecord {
+        let mut values: HashMap<String, String> = HashMap::new();
+        values.insert("ts_code".to_owned(), "000001.SZ".to_owned());
+        values.insert("ann_date".to_owned(), ann_date.to_owned());
+        values.insert("end_date".to_owned(), end_date.to_owned());
+        values.insert("update_flag".to_owned(), update_flag.to_owned());
+        values.insert("n_income".to_owned(), n_income.to_owned());
+        FinancialRecord::from_record("income", Record { values }).unwrap()
+    }
+
+    #[test]
+    fn test_as_of() {
+        let records = vec![
+            record("20210130", "20201231", "0", "100"),
+            record("20210130", "20201231", "1", "101"),
+            record("20210420", "20210331", "0", "30"),
+            // restated later
+            record("20210801", "20201231", "1", "90"),
+        ];
+
+        assert!(as_of(&records, "20210130").is_empty());
+
+        let known = as_of(&records, "20210201");
+        assert_eq!(known.len(), 1);
+        assert_eq!(known[0].value("n_income"), Some(101.0));
+
+        let known = as_of(&records, "20210421");
+        assert_eq!(known.len(), 2);
+        assert_eq!(known[1].end_date, "20210331");
+
+        let known = as_of(&records, "20210901");
+        assert_eq!(known[0].value("n_income"), Some(90.0));
+    }
+
+    #[test]
+    fn test_read_statement() {
+        let date_dir = std::env::temp_dir().join("choose_some_test_read_statement");
+        if date_dir.exists() {
+            fs::remove_dir_all(&date_dir).unwrap();
+        }
+        let statement = statement("fina_indicator").unwrap();
+        fs::create_dir_all(date_dir.join(statement.dir)).unwrap();
+
+        let fields: Vec<String> = vec!["end_date", "ts_code", "ann_date", "roe"]
+            .into_iter()
+            .map(|f| f.to_owned())
+            .collect();
+        let item = vec![
+            serde_json::json!("20201231"),
+            serde_json::json!("000001.SZ"),
+            serde_json::json!("20210202"),
+            serde_json::json!(9.58),
+        ];
+        let fina_indicator = dataset::dataset("fina_indicator").unwrap();
+        let row = fina_indicator.row(&fields, &item).unwrap().unwrap();
+        fs::write(
+            date_dir.join(statement.dir).join("000001.SZ"),
+            format!("{}\n{}\n", fina_indicator.header(), row.join("\t")),
+        )
+        .unwrap();
+
+        let records = load_as_of(&date_dir, "fina_indicator", "000001.SZ", "20210203").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].value("roe"), Some(9.58));
+        assert_eq!(records[0].value("eps"), None);
+        assert!(read_statement(&date_dir, "daily", "000001.SZ").is_err());
+
+        fs::remove_dir_all(&date_dir).unwrap();
+    }
+}
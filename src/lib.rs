@@ -1,10 +1,26 @@
+use std::collections::HashMap;
 use std::env;
 use std::str::FromStr;
 use structopt::StructOpt;
 
 mod analysis;
+pub mod bars;
+pub mod calendar;
+mod cassette;
+mod checkpoint;
 mod crawl;
+pub mod dataset;
+mod fetch;
+pub mod financial;
 mod models;
+mod plan;
+mod rate_limit;
+mod retry;
+mod source;
+pub mod trading_status;
+mod tushare;
+mod universe;
+mod validate;
 mod metrics;
 mod test2;
 mod test1;
@@ -12,6 +28,12 @@ mod test3;
 mod test;
 pub mod testt;
 
+use cassette::CassetteMode;
+use fetch::FetchRequest;
+use rate_limit::AccountTier;
+use source::Source;
+use universe::Universe;
+
 /// download stocks data and analysis for buy or sell.
 #[derive(StructOpt)]
 pub struct Opt {
@@ -23,9 +45,96 @@ pub struct Opt {
     #[structopt(short = "e", long = "data-end-date", default_value = "20210901")]
     data_end_date: String,
 
-    /// download data type
+    /// download data type: all is daily and daily_basic, or datasets and groups like daily,fund,financial
     #[structopt(short = "t", long = "download-type", default_value = "all")]
     download_type: DownloadType,
+
+    /// run mode: full re-downloads start to end date, update appends missing trade days to latest data
+    #[structopt(short = "m", long = "mode", default_value = "full")]
+    run_mode: RunMode,
+
+    /// tushare api url, default env TUSHARE_API_URL or http://api.waditu.com
+    #[structopt(long = "tushare-api-url")]
+    tushare_api_url: Option<String>,
+
+    /// tushare request timeout seconds
+    #[structopt(long = "request-timeout", default_value = "30")]
+    request_timeout: u64,
+
+    /// tushare account points: 120, 2000, 5000 or 10000, decides default calls per minute
+    #[structopt(long = "account-tier", default_value = "5000")]
+    account_tier: AccountTier,
+
+    /// calls per minute of one api, like daily=500, can be given many times
+    #[structopt(long = "rate-limit")]
+    rate_limit: Vec<String>,
+
+    /// max retries of one tushare call on network error, rate limited or server busy
+    #[structopt(long = "max-retries", default_value = "5")]
+    max_retries: u32,
+
+    /// how many stock groups download at the same time, all under the rate limit
+    #[structopt(short = "c", long = "concurrency", default_value = "8")]
+    concurrency: usize,
+
+    /// only print the request plan, no download
+    #[structopt(long = "plan")]
+    plan_only: bool,
+
+    /// stocks to download, exchange:market pairs like SSE:主板,SZSE:创业板, SZSE for all its boards, or all
+    #[structopt(long = "markets", default_value = "SSE:主板,SZSE:主板")]
+    markets: Universe,
+
+    /// list status of stocks and funds to download: L listed, D delisted, P paused, like L,D,P for history without survivorship bias
+    #[structopt(long = "list-status", default_value = "L", use_delimiter = true)]
+    list_status: Vec<ListStatus>,
+
+    /// index codes of download type index, default CSI 300, SSE Composite, CSI 500 and ChiNext
+    #[structopt(
+        long = "index-codes",
+        default_value = "000300.SH,000001.SH,000905.SH,399006.SZ",
+        use_delimiter = true
+    )]
+    index_codes: Vec<String>,
+
+    /// where stocks list, calendar, daily and daily_basic come from: tushare,
+    /// or csv:<dir> for a local dir of csv files, other download types need tushare
+    #[structopt(long = "source", default_value = "tushare")]
+    source: Source,
+
+    /// record:<file> saves every tushare call with its response to a cassette file,
+    /// replay:<file> answers tushare calls from the file without network, for tests
+    #[structopt(long = "cassette")]
+    cassette: Option<CassetteMode>,
+
+    /// refuse to mark a download finished if validation finds any issue
+    #[structopt(long = "strict")]
+    strict: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+pub enum Command {
+    /// pull one tushare api into a tsv file with a header, for apis not downloaded
+    Fetch {
+        /// tushare api name, like namechange
+        #[structopt(long = "api")]
+        api: String,
+
+        /// api param like ts_code=600000.SH, can be given many times
+        #[structopt(long = "param")]
+        param: Vec<String>,
+
+        /// fields separated by comma, the api default fields if not given
+        #[structopt(long = "fields", default_value = "")]
+        fields: String,
+
+        /// tsv file to write, stdout if not given
+        #[structopt(long = "out")]
+        out: Option<String>,
+    },
 }
 #[derive(Debug, PartialEq)]
 pub struct Config {
@@ -34,23 +143,105 @@ pub struct Config {
     pub tushare_token: String,
     pub data_dir: String,
     pub download_type: DownloadType,
+    pub run_mode: RunMode,
+    pub tushare_api_url: String,
+    pub request_timeout: u64,
+    pub account_tier: AccountTier,
+    pub rate_limits: HashMap<String, u32>,
+    pub max_retries: u32,
+    pub concurrency: usize,
+    pub plan_only: bool,
+    pub universe: Universe,
+    pub list_statuses: Vec<ListStatus>,
+    pub index_codes: Vec<String>,
+    pub source: Source,
+    pub cassette: Option<CassetteMode>,
+    pub strict: bool,
+    pub fetch: Option<FetchRequest>,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// --download-type, all or registered dataset names and groups like fund, separated by comma.
+/// all is the datasets in_all of the registry, the others are opt-in
+#[derive(Debug, PartialEq, Clone)]
 pub enum DownloadType {
     All,
-    Daily,
-    DailyBasic,
+    Datasets(Vec<&'static str>),
 }
 type ParseError = &'static str;
 impl FromStr for DownloadType {
     type Err = ParseError;
     fn from_str(download_type: &str) -> Result<Self, Self::Err> {
-        match download_type {
-            "daily" => Ok(DownloadType::Daily),
-            "daily_basic" => Ok(DownloadType::DailyBasic),
-            "all" => Ok(DownloadType::All),
-            _ => Err("Could not parse download-type"),
+        if download_type.trim() == "all" {
+            return Ok(DownloadType::All);
+        }
+        let mut names: Vec<&'static str> = vec![];
+        for name in download_type.split(',') {
+            let datasets = dataset::named(name.trim());
+            if datasets.is_empty() {
+                return Err("Could not parse download-type, should be all or registered datasets");
+            }
+            for dataset in datasets {
+                if !names.contains(&dataset.name) {
+                    names.push(dataset.name);
+                }
+            }
+        }
+        Ok(DownloadType::Datasets(names))
+    }
+}
+impl DownloadType {
+    /// selected datasets in registry order
+    pub fn datasets(&self) -> Vec<&'static dataset::Dataset> {
+        match self {
+            DownloadType::All => dataset::DATASETS.iter().filter(|d| d.in_all).collect(),
+            DownloadType::Datasets(names) => dataset::DATASETS
+                .iter()
+                .filter(|d| names.contains(&d.name))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RunMode {
+    Full,
+    Update,
+}
+impl FromStr for RunMode {
+    type Err = ParseError;
+    fn from_str(run_mode: &str) -> Result<Self, Self::Err> {
+        match run_mode {
+            "full" => Ok(RunMode::Full),
+            "update" => Ok(RunMode::Update),
+            _ => Err("Could not parse mode"),
+        }
+    }
+}
+
+/// list_status of tushare stock_basic
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ListStatus {
+    Listed,
+    Delisted,
+    Paused,
+}
+impl ListStatus {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ListStatus::Listed => "L",
+            ListStatus::Delisted => "D",
+            ListStatus::Paused => "P",
+        }
+    }
+}
+impl FromStr for ListStatus {
+    type Err = ParseError;
+    fn from_str(list_status: &str) -> Result<Self, Self::Err> {
+        match list_status.trim() {
+            "L" => Ok(ListStatus::Listed),
+            "D" => Ok(ListStatus::Delisted),
+            "P" => Ok(ListStatus::Paused),
+            _ => Err("Could not parse list-status, should be L, D or P"),
         }
     }
 }
@@ -65,31 +256,110 @@ impl Config {
             return Err(result);
         }
 
-        let tushare_token = env::var("TUSHARE_TOKEN").unwrap();
-        if tushare_token.eq("") {
+        // a csv source or a replayed cassette reads no tushare
+        let tushare_token = env::var("TUSHARE_TOKEN").unwrap_or_default();
+        let replay = matches!(args.cassette, Some(CassetteMode::Replay(_)));
+        if tushare_token.is_empty() && args.source == Source::Tushare && !replay {
             return Err(String::from("NO TUSHARE_TOKEN!"));
         }
 
-        let data_dir = env::var("DATA_DIR").unwrap();
-        if data_dir.eq("") {
+        let fetch = match args.command {
+            Some(Command::Fetch {
+                api,
+                param,
+                fields,
+                out,
+            }) => {
+                let mut params: Vec<(String, String)> = vec![];
+                for a_param in &param {
+                    params.push(fetch::parse_param(a_param)?);
+                }
+                Some(FetchRequest {
+                    api_name: api,
+                    params,
+                    fields,
+                    out,
+                })
+            }
+            None => None,
+        };
+
+        // fetch writes no data dir
+        let data_dir = env::var("DATA_DIR").unwrap_or_default();
+        if data_dir.is_empty() && fetch.is_none() {
             return Err(String::from("NO DATA_DIR!"));
         }
 
         let download_type = args.download_type;
 
+        let tushare_api_url = match args.tushare_api_url {
+            Some(url) => url,
+            None => env::var("TUSHARE_API_URL")
+                .unwrap_or_else(|_| tushare::DEFAULT_API_URL.to_owned()),
+        };
+        if tushare_api_url.is_empty() {
+            return Err(String::from("NO TUSHARE_API_URL!"));
+        }
+
+        let mut rate_limits: HashMap<String, u32> = HashMap::new();
+        for a_rate_limit in &args.rate_limit {
+            let (api_name, calls_per_minute) = rate_limit::parse_rate_limit(a_rate_limit)?;
+            rate_limits.insert(api_name, calls_per_minute);
+        }
+
+        let mut list_statuses: Vec<ListStatus> = vec![];
+        for list_status in args.list_status {
+            if !list_statuses.contains(&list_status) {
+                list_statuses.push(list_status);
+            }
+        }
+        if list_statuses.is_empty() {
+            return Err(String::from("NO list status!"));
+        }
+
+        let mut index_codes: Vec<String> = vec![];
+        for index_code in args.index_codes {
+            let index_code = index_code.trim().to_owned();
+            if !index_code.is_empty() && !index_codes.contains(&index_code) {
+                index_codes.push(index_code);
+            }
+        }
+
         Ok(Config {
             data_start_date,
             data_end_date,
             tushare_token,
             data_dir,
             download_type,
+            run_mode: args.run_mode,
+            tushare_api_url,
+            request_timeout: args.request_timeout,
+            account_tier: args.account_tier,
+            rate_limits,
+            max_retries: args.max_retries,
+            concurrency: args.concurrency.max(1),
+            plan_only: args.plan_only,
+            universe: args.markets,
+            list_statuses,
+            index_codes,
+            source: args.source,
+            cassette: args.cassette,
+            strict: args.strict,
+            fetch,
         })
     }
 }
 
 pub fn run(config: &mut Config) -> Result<(), String> {
+    if let Some(request) = &config.fetch {
+        return fetch::run(config, request).map_err(|e| e.to_string());
+    }
     println!("{} {}", config.data_start_date, config.data_end_date);
-    let (earliest_trade_date, latest_trade_date) = crawl::run(config).unwrap();
+    // a strict download that fails validation returns the report summary here
+    let (earliest_trade_date, latest_trade_date) = crawl::run(config).map_err(|e| e.to_string())?;
+    if config.plan_only {
+        return Ok(());
+    }
     config.data_start_date = earliest_trade_date;
     config.data_end_date = latest_trade_date;
     println!("{} {}", config.data_start_date, config.data_end_date);
@@ -109,6 +379,21 @@ mod tests {
             data_start_date: String::from("20210101"),
             data_end_date: String::from("20210901"),
             download_type: DownloadType::All,
+            run_mode: RunMode::Full,
+            tushare_api_url: None,
+            request_timeout: 30,
+            account_tier: AccountTier::Points5000,
+            rate_limit: vec![],
+            max_retries: 5,
+            concurrency: 8,
+            plan_only: false,
+            markets: "SSE:主板,SZSE:主板".parse().unwrap(),
+            list_status: vec![ListStatus::Listed],
+            index_codes: vec![String::from("000300.SH")],
+            source: Source::Tushare,
+            cassette: None,
+            strict: false,
+            command: None,
         };
         let config = Config::new(args).unwrap();
         let tushare_token = env::var("TUSHARE_TOKEN").unwrap();
@@ -122,6 +407,21 @@ mod tests {
                 tushare_token: tushare_token,
                 data_dir: data_dir,
                 download_type: DownloadType::All,
+                run_mode: RunMode::Full,
+                tushare_api_url: String::from(tushare::DEFAULT_API_URL),
+                request_timeout: 30,
+                account_tier: AccountTier::Points5000,
+                rate_limits: HashMap::new(),
+                max_retries: 5,
+                concurrency: 8,
+                plan_only: false,
+                universe: "SSE:主板,SZSE:主板".parse().unwrap(),
+                list_statuses: vec![ListStatus::Listed],
+                index_codes: vec![String::from("000300.SH")],
+                source: Source::Tushare,
+                cassette: None,
+                strict: false,
+                fetch: None,
             }
         );
 
@@ -133,7 +433,36 @@ mod tests {
                 tushare_token: String::from(""),
                 data_dir: String::from(""),
                 download_type: DownloadType::All,
+                run_mode: RunMode::Full,
+                tushare_api_url: String::from(tushare::DEFAULT_API_URL),
+                request_timeout: 30,
+                account_tier: AccountTier::Points5000,
+                rate_limits: HashMap::new(),
+                max_retries: 5,
+                concurrency: 8,
+                plan_only: false,
+                universe: "SSE:主板,SZSE:主板".parse().unwrap(),
+                list_statuses: vec![ListStatus::Listed],
+                index_codes: vec![String::from("000300.SH")],
+                source: Source::Tushare,
+                cassette: None,
+                strict: false,
+                fetch: None,
             }
         );
     }
+    #[test]
+    fn parse_download_type() {
+        assert_eq!("all".parse::<DownloadType>().unwrap(), DownloadType::All);
+        let names: Vec<&str> = DownloadType::All.datasets().iter().map(|d| d.name).collect();
+        assert_eq!(names, vec!["daily", "daily_basic"]);
+        assert_eq!(
+            "daily, fund,fund_adj".parse::<DownloadType>().unwrap(),
+            DownloadType::Datasets(vec!["daily", "fund_daily", "fund_adj"])
+        );
+        let download_type: DownloadType = "moneyflow,suspend".parse().unwrap();
+        let names: Vec<&str> = download_type.datasets().iter().map(|d| d.name).collect();
+        assert_eq!(names, vec!["suspend_d", "moneyflow"]);
+        assert!("daily,minute".parse::<DownloadType>().is_err());
+    }
 }
@@ -1,5 +1,11 @@
+use crate::bars;
+use crate::dataset;
+use crate::tushare::null_as_default;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
 
 #[derive(Serialize, Deserialize)]
 pub struct TushareRESTfulAPI {
@@ -9,22 +15,35 @@ pub struct TushareRESTfulAPI {
     pub fields: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct StockBasic {
     pub ts_code: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub symbol: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub name: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub area: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub industry: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub fullname: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub enname: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub cnspell: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub market: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub exchange: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub curr_type: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub list_status: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub list_date: String,
     pub delist_date: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub is_hs: String,
 }
 
@@ -92,7 +111,7 @@ impl StockBasic {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct StockDaily {
     pub ts_code: String,
     pub trade_date: String,
@@ -103,33 +122,127 @@ pub struct StockDaily {
     pub pre_close: f64,
     pub change: f64,
     pub pct_chg: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub vol: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub amount: f64,
 }
 
-impl StockDaily {
-    fn to_vec(&self) -> Vec<String> {
-        vec![
-            String::from(self.ts_code.clone()),
-            String::from(self.trade_date.clone()),
-            self.open.to_string(),
-            self.high.to_string(),
-            self.low.to_string(),
-            self.close.to_string(),
-            self.pre_close.to_string(),
-            self.change.to_string(),
-            self.pct_chg.to_string(),
-            self.vol.to_string(),
-            self.amount.to_string(),
-        ]
+/// 复权因子 of one stock on one trade day
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StockAdjFactor {
+    pub ts_code: String,
+    pub trade_date: String,
+    pub adj_factor: f64,
+}
+
+/// one index of index_basic, its daily bars are StockDaily
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct IndexBasic {
+    pub ts_code: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub name: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub market: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub publisher: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub category: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub base_date: String,
+    pub base_point: Option<f64>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub list_date: String,
+}
+
+impl fmt::Display for IndexBasic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.ts_code,
+            self.name,
+            self.market,
+            self.publisher,
+            self.category,
+            self.base_date,
+            match self.base_point {
+                Some(base_point) => base_point.to_string(),
+                None => "none".to_owned(),
+            },
+            self.list_date
+        )
     }
+}
 
-    pub fn to_string(&self) -> String {
-        self.to_vec().join("\t")
+/// one exchange listed fund of fund_basic, ETF or LOF, its bars are StockDaily
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FundBasic {
+    pub ts_code: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub name: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub management: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub fund_type: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub invest_type: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub market: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub status: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub list_date: String,
+    pub delist_date: Option<String>,
+}
+
+impl fmt::Display for FundBasic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.ts_code,
+            self.name,
+            self.management,
+            self.fund_type,
+            self.invest_type,
+            self.market,
+            self.status,
+            self.list_date,
+            self.delist_date.as_deref().unwrap_or("none")
+        )
     }
 }
 
-#[derive(Debug, Clone)]
+/// one implemented (实施) dividend of one stock, per share before tax,
+/// stk_div is bonus and transfer shares (送股 + 转增) per share
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StockDividend {
+    pub ts_code: String,
+    pub ex_date: String,
+    pub end_date: String,
+    pub ann_date: String,
+    pub record_date: String,
+    pub pay_date: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub stk_div: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub cash_div_tax: f64,
+}
+
+/// one suspend_d row, suspend_type S is a suspended day, R is the day it resumes,
+/// suspend_timing is set when only part of the day is suspended
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StockSuspend {
+    pub ts_code: String,
+    pub trade_date: String,
+    pub suspend_timing: Option<String>,
+    pub suspend_type: String,
+}
+
+/// 每日指标 of one stock on one trade day, share in 万股, mv in 万元,
+/// an indicator tushare has no value of, like pe of a loss, is None
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct StockDailyBasic {
     pub ts_code: String,
     pub trade_date: String,
@@ -152,73 +265,49 @@ pub struct StockDailyBasic {
     pub limit_status: Option<i64>,
 }
 
-impl StockDailyBasic {
-    fn to_vec(&self) -> Vec<String> {
-        vec![
-            String::from(self.ts_code.clone()),
-            String::from(self.trade_date.clone()),
-            self.close.to_string(),
-            self.turnover_rate.to_string(),
-            if self.turnover_rate_f.is_none() {
-                "none".to_owned()
-            } else {
-                self.turnover_rate_f.unwrap().to_string()
-            },
-            if self.volume_ratio.is_none() {
-                "none".to_owned()
-            } else {
-                self.volume_ratio.unwrap().to_string()
-            },
-            if self.pe.is_none() {
-                "none".to_owned()
-            } else {
-                self.pe.unwrap().to_string()
-            },
-            if self.pe_ttm.is_none() {
-                "none".to_owned()
-            } else {
-                self.pe_ttm.unwrap().to_string()
-            },
-            if self.pb.is_none() {
-                "none".to_owned()
-            } else {
-                self.pb.unwrap().to_string()
-            },
-            if self.ps.is_none() {
-                "none".to_owned()
-            } else {
-                self.ps.unwrap().to_string()
-            },
-            if self.ps_ttm.is_none() {
-                "none".to_owned()
-            } else {
-                self.ps_ttm.unwrap().to_string()
-            },
-            if self.dv_ratio.is_none() {
-                "none".to_owned()
-            } else {
-                self.dv_ratio.unwrap().to_string()
-            },
-            if self.dv_ttm.is_none() {
-                "none".to_owned()
-            } else {
-                self.dv_ttm.unwrap().to_string()
-            },
-            self.total_share.to_string(),
-            self.float_share.to_string(),
-            self.free_share.to_string(),
-            self.total_mv.to_string(),
-            self.circ_mv.to_string(),
-            if self.limit_status.is_none() {
-                "none".to_owned()
-            } else {
-                self.limit_status.unwrap().to_string()
-            },
-        ]
-    }
+/// 涨跌停价 of one stock on one trade day
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StockLimit {
+    pub ts_code: String,
+    pub trade_date: String,
+    pub up_limit: f64,
+    pub down_limit: f64,
+}
 
-    pub fn to_string(&self) -> String {
-        self.to_vec().join("\t")
+/// 个股资金流向 of one stock on one trade day, vol in 手, amount in 万元,
+/// sm < 5万, md 5万-20万, lg 20万-100万, elg >= 100万 of one order
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StockMoneyflow {
+    pub ts_code: String,
+    pub trade_date: String,
+    pub buy_sm_vol: Option<f64>,
+    pub buy_sm_amount: Option<f64>,
+    pub sell_sm_vol: Option<f64>,
+    pub sell_sm_amount: Option<f64>,
+    pub buy_md_vol: Option<f64>,
+    pub buy_md_amount: Option<f64>,
+    pub sell_md_vol: Option<f64>,
+    pub sell_md_amount: Option<f64>,
+    pub buy_lg_vol: Option<f64>,
+    pub buy_lg_amount: Option<f64>,
+    pub sell_lg_vol: Option<f64>,
+    pub sell_lg_amount: Option<f64>,
+    pub buy_elg_vol: Option<f64>,
+    pub buy_elg_amount: Option<f64>,
+    pub sell_elg_vol: Option<f64>,
+    pub sell_elg_amount: Option<f64>,
+    pub net_mf_vol: Option<f64>,
+    pub net_mf_amount: Option<f64>,
+}
+
+impl StockMoneyflow {
+    /// net inflow amount of large and extra large orders
+    pub fn net_lg_elg_amount(&self) -> Option<f64> {
+        Some(
+            self.buy_lg_amount? + self.buy_elg_amount?
+                - self.sell_lg_amount?
+                - self.sell_elg_amount?,
+        )
     }
 }
 
@@ -231,7 +320,8 @@ pub struct AnalysisResult {
 pub struct Position {
     pub ts_code: String,
     pub trade_date: String,
-    pub price: i64,
+    // cost per share, bonus shares lower it without changing the cost
+    pub price: f64,
     pub volume: i64,
 }
 
@@ -239,6 +329,7 @@ pub struct Position {
 pub struct Wallet {
     pub start_date: String,
     pub start_value: i64,
+    pub cash: f64,
     pub current_positions: Vec<Position>,
 }
 
@@ -248,14 +339,64 @@ impl Wallet {
         Wallet {
             start_date,
             start_value,
+            cash: start_value as f64,
             current_positions,
         }
     }
+
+    /// step of one trade day before trading, positions are in raw prices:
+    /// dividends of the held stocks in dividend_data of date dir going ex on the day
+    /// are applied, a stock without dividend file has none. returns cash credited.
+    pub fn on_trade_date(
+        &mut self,
+        date_dir: &Path,
+        trade_date: &str,
+    ) -> Result<f64, Box<dyn Error>> {
+        let held: BTreeSet<&str> = self
+            .current_positions
+            .iter()
+            .map(|p| p.ts_code.as_str())
+            .collect();
+        let mut dividends: Vec<StockDividend> = vec![];
+        for ts_code in held {
+            if dataset::has_file(date_dir, "dividend", ts_code) {
+                dividends.extend(bars::read_dividends(date_dir, ts_code)?);
+            }
+        }
+        Ok(self.apply_dividends(trade_date, &dividends))
+    }
+
+    /// apply dividends whose ex-date is trade_date to positions bought before it:
+    /// cash dividend is credited before tax, bonus shares are added, rounded down,
+    /// and the position price is lowered to keep its cost. returns cash credited.
+    fn apply_dividends(&mut self, trade_date: &str, dividends: &[StockDividend]) -> f64 {
+        let mut credited = 0.0;
+        for dividend in dividends.iter().filter(|d| d.ex_date == trade_date) {
+            for position in self
+                .current_positions
+                .iter_mut()
+                .filter(|p| p.ts_code == dividend.ts_code && p.trade_date.as_str() < trade_date)
+            {
+                let cash = position.volume as f64 * dividend.cash_div_tax;
+                credited += cash;
+                let bonus = (position.volume as f64 * dividend.stk_div).floor() as i64;
+                if bonus > 0 {
+                    let cost = position.price * position.volume as f64;
+                    position.volume += bonus;
+                    position.price = cost / position.volume as f64;
+                }
+            }
+        }
+        self.cash += credited;
+        credited
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dataset::Record;
+    use std::fs;
 
     #[test]
     #[ignore]
@@ -267,4 +408,90 @@ mod tests {
         assert_eq!(wallet.start_value, start_value);
         assert_eq!(wallet.current_positions.len(), 0);
     }
+
+    #[test]
+    fn test_moneyflow_record() {
+        let moneyflow = dataset::dataset("moneyflow").unwrap();
+        let line = "000001.SZ\t20210104\t1\t2\t3\t4\t5\t6\t7\t8\t9\t10\t11\t12\t13\t14\t15\t16\tnone\t-3.5";
+        let record = Record {
+            values: moneyflow
+                .fields
+                .iter()
+                .zip(line.split('\t'))
+                .map(|(f, v)| (f.to_string(), v.to_owned()))
+                .collect(),
+        };
+        let moneyflow: StockMoneyflow = record.decode().unwrap();
+        assert_eq!(moneyflow.net_mf_vol, None);
+        assert_eq!(moneyflow.net_mf_amount, Some(-3.5));
+        assert_eq!(
+            moneyflow.net_lg_elg_amount(),
+            Some(10.0 + 14.0 - 12.0 - 16.0)
+        );
+
+        let mut record = record;
+        record.values.remove("ts_code");
+        assert!(record.decode::<StockMoneyflow>().is_err());
+        record
+            .values
+            .insert("ts_code".to_owned(), "000001.SZ".to_owned());
+        record
+            .values
+            .insert("buy_sm_vol".to_owned(), "x".to_owned());
+        assert!(record.decode::<StockMoneyflow>().is_err());
+    }
+
+    #[test]
+    fn test_on_trade_date() {
+        let date_dir = std::env::temp_dir().join("choose_some_test_on_trade_date");
+        if date_dir.exists() {
+            fs::remove_dir_all(&date_dir).unwrap();
+        }
+        fs::create_dir_all(date_dir.join("dividend_data")).unwrap();
+        let dividend = dataset::dataset("dividend").unwrap();
+        // 10 shares get 1 bonus share and 1.8 cash
+        fs::write(
+            date_dir.join("dividend_data").join("000001.SZ"),
+            format!(
+                "{}\n000001.SZ\t20210714\t20201231\t20210708\t20210713\t20210714\t0.1\t0.18\n",
+                dividend.header()
+            ),
+        )
+        .unwrap();
+
+        let mut wallet = Wallet::new(String::from("20210101"), 10000);
+        wallet.current_positions.push(Position {
+            ts_code: String::from("000001.SZ"),
+            trade_date: String::from("20210104"),
+            price: 12.0,
+            volume: 1000,
+        });
+        wallet.current_positions.push(Position {
+            ts_code: String::from("000001.SZ"),
+            trade_date: String::from("20210714"),
+            price: 12.0,
+            volume: 100,
+        });
+        // no dividend file
+        wallet.current_positions.push(Position {
+            ts_code: String::from("000002.SZ"),
+            trade_date: String::from("20210104"),
+            price: 30.0,
+            volume: 100,
+        });
+
+        assert_eq!(wallet.on_trade_date(&date_dir, "20210713").unwrap(), 0.0);
+        let credited = wallet.on_trade_date(&date_dir, "20210714").unwrap();
+        assert!((credited - 180.0).abs() < 1e-9);
+        assert!((wallet.cash - 10180.0).abs() < 1e-9);
+        let position = &wallet.current_positions[0];
+        assert_eq!(position.volume, 1100);
+        // cost 12000 is kept, 10.909 a share
+        assert!((position.price * position.volume as f64 - 12000.0).abs() < 1e-6);
+        // bought on the ex-date, no dividend
+        assert_eq!(wallet.current_positions[1].volume, 100);
+        assert_eq!(wallet.current_positions[2].volume, 100);
+
+        fs::remove_dir_all(&date_dir).unwrap();
+    }
 }
@@ -0,0 +1,133 @@
+/// Request plan of one download
+/// tushare returns at most 5000 rows every call, one row is one stock on one trade day,
+/// so codes in one call * trade days in one call must be <= 5000.
+/// short ranges put many codes in one call, ranges longer than 5000 trade days are split into windows.
+use std::fmt;
+
+pub const MAX_ROWS_PER_CALL: usize = 5000;
+// keep ts_code param of one call short
+pub const MAX_CODES_PER_CALL: usize = 200;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DateWindow {
+    pub start_date: String,
+    pub end_date: String,
+    pub trade_days: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DownloadPlan {
+    pub stocks: usize,
+    pub trade_days: usize,
+    pub codes_per_call: usize,
+    pub groups: Vec<Vec<String>>,
+    pub windows: Vec<DateWindow>,
+}
+
+impl DownloadPlan {
+    /// trade_dates are the open days of the range
+    pub fn new(ts_codes: &[String], trade_dates: &[String], max_rows: usize) -> DownloadPlan {
+        let mut trade_dates = trade_dates.to_vec();
+        trade_dates.sort();
+        trade_dates.dedup();
+
+        let trade_days = trade_dates.len();
+        let mut windows: Vec<DateWindow> = vec![];
+        for window_dates in trade_dates.chunks(max_rows.max(1)) {
+            windows.push(DateWindow {
+                start_date: window_dates[0].clone(),
+                end_date: window_dates[window_dates.len() - 1].clone(),
+                trade_days: window_dates.len(),
+            });
+        }
+        let longest_window = windows.iter().map(|w| w.trade_days).max().unwrap_or(1);
+        let codes_per_call = (max_rows / longest_window).clamp(1, MAX_CODES_PER_CALL);
+
+        let groups: Vec<Vec<String>> = ts_codes
+            .chunks(codes_per_call)
+            .map(|group| group.to_vec())
+            .collect();
+
+        DownloadPlan {
+            stocks: ts_codes.len(),
+            trade_days,
+            codes_per_call,
+            groups,
+            windows,
+        }
+    }
+
+    /// calls of one stock groups dataset, other datasets have their own calls, see crawl::download_calls
+    pub fn calls(&self) -> usize {
+        self.groups.len() * self.windows.len()
+    }
+
+    /// at most one row a stock a trade day, suspended days have no row
+    pub fn expected_rows(&self) -> usize {
+        self.stocks * self.trade_days
+    }
+}
+
+impl fmt::Display for DownloadPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "stocks: {}", self.stocks)?;
+        writeln!(f, "trade days: {}", self.trade_days)?;
+        writeln!(f, "codes per call: {}", self.codes_per_call)?;
+        writeln!(f, "groups: {}", self.groups.len())?;
+        for window in &self.windows {
+            writeln!(
+                f,
+                "window: {} - {}, {} trade days",
+                window.start_date, window.end_date, window.trade_days
+            )?;
+        }
+        writeln!(f, "calls per stock groups dataset: {}", self.calls())?;
+        write!(
+            f,
+            "expected rows per stock groups dataset: {}",
+            self.expected_rows()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts_codes(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("{:06}.SZ", i)).collect()
+    }
+
+    fn trade_dates(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("{:08}", 20000000 + i)).collect()
+    }
+
+    #[test]
+    fn test_plan_one_window() {
+        let plan = DownloadPlan::new(&ts_codes(45), &trade_dates(480), MAX_ROWS_PER_CALL);
+        assert_eq!(plan.codes_per_call, 10);
+        assert_eq!(plan.groups.len(), 5);
+        assert_eq!(plan.groups[4].len(), 5);
+        assert_eq!(plan.windows.len(), 1);
+        assert_eq!(plan.calls(), 5);
+        assert_eq!(plan.expected_rows(), 45 * 480);
+    }
+
+    #[test]
+    fn test_plan_split_windows() {
+        let plan = DownloadPlan::new(&ts_codes(3), &trade_dates(12), 5);
+        assert_eq!(plan.codes_per_call, 1);
+        assert_eq!(plan.windows.len(), 3);
+        assert_eq!(plan.windows[0].start_date, "20000000");
+        assert_eq!(plan.windows[0].end_date, "20000004");
+        assert_eq!(plan.windows[2].trade_days, 2);
+        assert_eq!(plan.calls(), 9);
+    }
+
+    #[test]
+    fn test_plan_max_codes() {
+        let plan = DownloadPlan::new(&ts_codes(1000), &trade_dates(1), MAX_ROWS_PER_CALL);
+        assert_eq!(plan.codes_per_call, MAX_CODES_PER_CALL);
+        assert_eq!(plan.groups.len(), 5);
+    }
+}
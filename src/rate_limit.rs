@@ -0,0 +1,195 @@
+/// Token bucket rate limiter for tushare api calls
+/// tushare limits calls per minute for every api, by account points.
+/// every api name has its own bucket, all shared in one limiter.
+use log::{debug, info};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// tushare account tier by points, decides default calls per minute
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AccountTier {
+    Points120,
+    Points2000,
+    Points5000,
+    Points10000,
+}
+
+impl AccountTier {
+    pub fn calls_per_minute(&self) -> u32 {
+        match self {
+            AccountTier::Points120 => 50,
+            AccountTier::Points2000 => 200,
+            AccountTier::Points5000 => 500,
+            AccountTier::Points10000 => 1000,
+        }
+    }
+}
+
+impl FromStr for AccountTier {
+    type Err = crate::ParseError;
+    fn from_str(tier: &str) -> Result<Self, Self::Err> {
+        match tier {
+            "120" => Ok(AccountTier::Points120),
+            "2000" => Ok(AccountTier::Points2000),
+            "5000" => Ok(AccountTier::Points5000),
+            "10000" => Ok(AccountTier::Points10000),
+            _ => Err("Could not parse account-tier, should be 120, 2000, 5000 or 10000"),
+        }
+    }
+}
+
+/// parse "api_name=calls_per_minute"
+pub fn parse_rate_limit(a_str: &str) -> Result<(String, u32), String> {
+    let mut kv = a_str.splitn(2, '=');
+    let api_name = kv.next().unwrap_or_default().trim();
+    let limit = kv.next().unwrap_or_default().trim();
+    if api_name.is_empty() {
+        return Err(format!("rate limit no api name: {}", a_str));
+    }
+    match limit.parse::<u32>() {
+        Ok(limit) if limit > 0 => Ok((api_name.to_owned(), limit)),
+        _ => Err(format!("rate limit calls per minute is error: {}", a_str)),
+    }
+}
+
+struct Bucket {
+    capacity: f64,
+    tokens: f64,
+    // tokens per second
+    refill_rate: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    // burst at most one second of calls, so one minute never goes much over the limit
+    fn new(calls_per_minute: u32) -> Bucket {
+        let refill_rate = calls_per_minute as f64 / 60.0;
+        let capacity = refill_rate.max(1.0).floor();
+        Bucket {
+            capacity,
+            tokens: capacity,
+            refill_rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    // take one token, or return how long to wait for it
+    fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.refill_rate,
+            ))
+        }
+    }
+}
+
+pub struct RateLimiter {
+    default_calls_per_minute: u32,
+    api_calls_per_minute: HashMap<String, u32>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    throttled: Mutex<Duration>,
+}
+
+impl RateLimiter {
+    pub fn new(tier: AccountTier, api_calls_per_minute: HashMap<String, u32>) -> RateLimiter {
+        RateLimiter {
+            default_calls_per_minute: tier.calls_per_minute(),
+            api_calls_per_minute,
+            buckets: Mutex::new(HashMap::new()),
+            throttled: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+
+    pub fn calls_per_minute(&self, api_name: &str) -> u32 {
+        *self
+            .api_calls_per_minute
+            .get(api_name)
+            .unwrap_or(&self.default_calls_per_minute)
+    }
+
+    /// block until one call of api_name is allowed
+    pub fn acquire(&self, api_name: &str) {
+        let mut waited = Duration::from_secs(0);
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let bucket = buckets
+                    .entry(api_name.to_owned())
+                    .or_insert_with(|| Bucket::new(self.calls_per_minute(api_name)));
+                match bucket.try_take(Instant::now()) {
+                    Ok(()) => break,
+                    Err(wait) => wait,
+                }
+            };
+            thread::sleep(wait);
+            waited += wait;
+        }
+
+        if waited > Duration::from_secs(0) {
+            let mut throttled = self.throttled.lock().unwrap();
+            *throttled += waited;
+            debug!("{} throttled {:?}", api_name, waited);
+            if waited >= Duration::from_secs(1) {
+                info!(
+                    "{} throttled {:?}, total throttled {:?}",
+                    api_name, waited, *throttled
+                );
+            }
+        }
+    }
+
+    /// total time all calls waited for the limit
+    pub fn throttled(&self) -> Duration {
+        *self.throttled.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!(
+            parse_rate_limit("daily=500").unwrap(),
+            ("daily".to_owned(), 500)
+        );
+        assert!(parse_rate_limit("daily").is_err());
+        assert!(parse_rate_limit("=500").is_err());
+        assert!(parse_rate_limit("daily=0").is_err());
+    }
+
+    #[test]
+    fn test_bucket_try_take() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(120);
+        assert_eq!(bucket.capacity, 2.0);
+        assert!(bucket.try_take(now).is_ok());
+        assert!(bucket.try_take(now).is_ok());
+        let wait = bucket.try_take(now).unwrap_err();
+        assert!(wait <= Duration::from_millis(500));
+        assert!(bucket.try_take(now + Duration::from_millis(500)).is_ok());
+    }
+
+    #[test]
+    fn test_calls_per_minute() {
+        let mut api_calls_per_minute = HashMap::new();
+        api_calls_per_minute.insert("daily_basic".to_owned(), 100);
+        let limiter = RateLimiter::new(AccountTier::Points5000, api_calls_per_minute);
+        assert_eq!(limiter.calls_per_minute("daily"), 500);
+        assert_eq!(limiter.calls_per_minute("daily_basic"), 100);
+    }
+}
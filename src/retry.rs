@@ -0,0 +1,57 @@
+/// Retry policy with jittered exponential backoff
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+
+    /// delay before retry number `attempt` (from 0): base * 2^attempt, at most max_delay,
+    /// then jittered into [delay / 2, delay] so many workers don't retry at the same time
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.min(16));
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        let half = delay / 2;
+        half + half.mul_f64(jitter())
+    }
+}
+
+// random number in [0, 1), RandomState is seeded randomly every time
+fn jitter() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::new(5);
+        for attempt in 0..10 {
+            let delay = policy.delay(attempt);
+            let full = (Duration::from_secs(1) * 2u32.pow(attempt)).min(policy.max_delay);
+            assert!(delay >= full / 2);
+            assert!(delay <= full);
+        }
+        assert!(policy.delay(100) <= policy.max_delay);
+    }
+}
@@ -0,0 +1,563 @@
+/// Where a download reads stocks list, calendar, daily and daily_basic from
+/// tushare is one source, a local dir of csv files exported from somewhere else is another:
+/// --stocks.csv , file means stock_basic fields of every stock
+/// --trade_cal.csv , file means exchange, cal_date, is_open, pretrade_date of every exchange
+/// --daily/<ts_code>.csv , file means daily fields of one stock
+/// --daily_basic/<ts_code>.csv , file means daily_basic fields of one stock
+/// csv files are comma separated without quotes, the first line names the columns in any order,
+/// an empty or missing optional column is none.
+/// either way the date dir written is the same, so analysis doesn't know where data came from:
+/// dataset rows come back in the field order of the dataset registry and are stored as they are.
+/// index and fund lists and datasets other than daily and daily_basic are only on tushare.
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::calendar::{CalendarDay, TradingCalendar};
+use crate::dataset::{Calls, Dataset, Partition};
+use crate::models::{FundBasic, IndexBasic, StockBasic};
+use crate::tushare::{TushareClient, TushareData};
+use crate::universe::Market;
+use crate::ListStatus;
+
+pub trait DataSource: Send + Sync {
+    fn name(&self) -> String;
+
+    /// stocks of one market in one list status
+    fn stocks_basic(
+        &self,
+        market: &Market,
+        list_status: ListStatus,
+    ) -> Result<Vec<StockBasic>, Box<dyn Error>>;
+
+    /// open and closed days of one exchange from start_date to end_date
+    fn trade_calendar(
+        &self,
+        exchange: &str,
+        start_date: &str,
+        end_date: &str,
+    ) -> Result<TradingCalendar, Box<dyn Error>>;
+
+    /// rows of a dataset of the codes from start_date to end_date.
+    /// the codes of a per date dataset are its trade date
+    fn dataset_rows(
+        &self,
+        dataset: &Dataset,
+        ts_codes: &[String],
+        start_date: &str,
+        end_date: &str,
+    ) -> Result<DatasetRows, Box<dyn Error>>;
+
+    /// indexes of the codes
+    fn index_basic(&self, _index_codes: &[String]) -> Result<Vec<IndexBasic>, Box<dyn Error>> {
+        Err(format!("{} has no index list, it needs tushare", self.name()).into())
+    }
+
+    /// exchange listed funds
+    fn fund_basic(&self) -> Result<Vec<FundBasic>, Box<dyn Error>> {
+        Err(format!("{} has no fund list, it needs tushare", self.name()).into())
+    }
+}
+
+/// stored rows of a dataset in the order of its fields,
+/// a row that doesn't fit the dataset is an error of why
+pub type DatasetRows = Vec<Result<Vec<String>, String>>;
+
+// rows of a response, a row of another only value is not one of the dataset
+fn dataset_rows(dataset: &Dataset, data: &TushareData) -> DatasetRows {
+    data.items
+        .iter()
+        .filter_map(|item| dataset.row(&data.fields, item).transpose())
+        .collect()
+}
+
+/// --source option
+#[derive(Debug, PartialEq, Clone)]
+pub enum Source {
+    Tushare,
+    Csv(String),
+}
+impl FromStr for Source {
+    type Err = crate::ParseError;
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match source.trim() {
+            "tushare" => Ok(Source::Tushare),
+            csv if csv.starts_with("csv:") && csv.len() > "csv:".len() => {
+                Ok(Source::Csv(csv["csv:".len()..].to_owned()))
+            }
+            _ => Err("Could not parse source, should be tushare or csv:<dir>"),
+        }
+    }
+}
+
+pub struct CsvSource {
+    dir: PathBuf,
+}
+
+impl CsvSource {
+    pub fn new(dir: &Path) -> Result<CsvSource, Box<dyn Error>> {
+        if !dir.is_dir() {
+            return Err(format!("csv source {:?} is not a dir", dir).into());
+        }
+        Ok(CsvSource {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    // rows of a dataset dir of the codes in the range, a code without file has no row
+    fn rows_in_range(
+        &self,
+        sub_dir: &str,
+        ts_codes: &[String],
+        start_date: &str,
+        end_date: &str,
+    ) -> Result<Vec<CsvRow>, Box<dyn Error>> {
+        let mut rows: Vec<CsvRow> = vec![];
+        for ts_code in ts_codes {
+            let file_name = self.dir.join(sub_dir).join(format!("{}.csv", ts_code));
+            if !file_name.exists() {
+                continue;
+            }
+            for row in read_csv(&file_name)? {
+                let trade_date = row.text("trade_date")?;
+                if trade_date.as_str() >= start_date && trade_date.as_str() <= end_date {
+                    rows.push(row);
+                }
+            }
+        }
+        Ok(rows)
+    }
+}
+
+impl DataSource for CsvSource {
+    fn name(&self) -> String {
+        format!("csv:{}", self.dir.display())
+    }
+
+    fn stocks_basic(
+        &self,
+        market: &Market,
+        list_status: ListStatus,
+    ) -> Result<Vec<StockBasic>, Box<dyn Error>> {
+        let mut stocks_basic: Vec<StockBasic> = vec![];
+        for row in read_csv(&self.dir.join("stocks.csv"))? {
+            let stock_basic = StockBasic {
+                ts_code: row.text("ts_code")?,
+                symbol: row.text_or_default("symbol"),
+                name: row.text_or_default("name"),
+                area: row.text_or_default("area"),
+                industry: row.text_or_default("industry"),
+                fullname: row.text_or_default("fullname"),
+                enname: row.text_or_default("enname"),
+                cnspell: row.text_or_default("cnspell"),
+                market: row.text_or_default("market"),
+                exchange: row.text_or_default("exchange"),
+                curr_type: row.text_or_default("curr_type"),
+                list_status: row.text_or_default("list_status"),
+                list_date: row.text("list_date")?,
+                delist_date: row.optional_text("delist_date"),
+                is_hs: row.text_or_default("is_hs"),
+            };
+            // stocks without list_status are listed ones
+            let status = match stock_basic.list_status.as_str() {
+                "" => "L",
+                status => status,
+            };
+            if market
+                .exchange
+                .as_ref()
+                .map_or(true, |e| *e == stock_basic.exchange)
+                && market
+                    .market
+                    .as_ref()
+                    .map_or(true, |m| *m == stock_basic.market)
+                && status == list_status.code()
+            {
+                stocks_basic.push(stock_basic);
+            }
+        }
+        Ok(stocks_basic)
+    }
+
+    fn trade_calendar(
+        &self,
+        exchange: &str,
+        start_date: &str,
+        end_date: &str,
+    ) -> Result<TradingCalendar, Box<dyn Error>> {
+        let mut days: Vec<CalendarDay> = vec![];
+        for row in read_csv(&self.dir.join("trade_cal.csv"))? {
+            let cal_date = row.text("cal_date")?;
+            if row.text("exchange")? != exchange
+                || cal_date.as_str() < start_date
+                || cal_date.as_str() > end_date
+            {
+                continue;
+            }
+            days.push(CalendarDay {
+                exchange: exchange.to_owned(),
+                cal_date,
+                is_open: row.text("is_open")? == "1",
+                pretrade_date: row.optional_text("pretrade_date"),
+            });
+        }
+        Ok(TradingCalendar::new(exchange, days))
+    }
+
+    // only datasets from_source, daily and daily_basic, in <dataset name>/<ts_code>.csv
+    fn dataset_rows(
+        &self,
+        dataset: &Dataset,
+        ts_codes: &[String],
+        start_date: &str,
+        end_date: &str,
+    ) -> Result<DatasetRows, Box<dyn Error>> {
+        if !dataset.from_source || dataset.partition == Partition::PerDate {
+            return Err(
+                format!("{} has no {}, it needs tushare", self.name(), dataset.name).into(),
+            );
+        }
+        let mut items: Vec<Vec<serde_json::Value>> = vec![];
+        for row in self.rows_in_range(dataset.name, ts_codes, start_date, end_date)? {
+            items.push(
+                dataset
+                    .fields
+                    .iter()
+                    .map(|f| match row.optional_text(f) {
+                        Some(value) => serde_json::Value::String(value),
+                        None => serde_json::Value::Null,
+                    })
+                    .collect(),
+            );
+        }
+        Ok(dataset_rows(
+            dataset,
+            &TushareData {
+                fields: dataset.fields.iter().map(|f| f.to_string()).collect(),
+                items,
+            },
+        ))
+    }
+}
+
+impl DataSource for TushareClient {
+    fn name(&self) -> String {
+        format!("tushare {}", self.base_url())
+    }
+
+    fn stocks_basic(
+        &self,
+        market: &Market,
+        list_status: ListStatus,
+    ) -> Result<Vec<StockBasic>, Box<dyn Error>> {
+        crawl_stocks_basic(self, market, list_status)
+    }
+
+    fn trade_calendar(
+        &self,
+        exchange: &str,
+        start_date: &str,
+        end_date: &str,
+    ) -> Result<TradingCalendar, Box<dyn Error>> {
+        crawl_trade_calendar(self, exchange, start_date, end_date)
+    }
+
+    // 每分钟内最多调取500次，每次5000条数据. the rate limiter and the plan keep calls under both.
+    fn dataset_rows(
+        &self,
+        dataset: &Dataset,
+        ts_codes: &[String],
+        start_date: &str,
+        end_date: &str,
+    ) -> Result<DatasetRows, Box<dyn Error>> {
+        let mut params: HashMap<String, String> = HashMap::new();
+        match dataset.partition {
+            Partition::PerDate => {
+                params.insert("trade_date".to_owned(), ts_codes[0].clone());
+            }
+            Partition::PerStock(calls) => {
+                params.insert("ts_code".to_owned(), ts_codes.join(","));
+                if calls != Calls::History {
+                    params.insert("start_date".to_owned(), start_date.to_owned());
+                    params.insert("end_date".to_owned(), end_date.to_owned());
+                }
+            }
+        }
+        let data = self.query(dataset.api_name, params, &dataset.fields_param())?;
+        Ok(dataset_rows(dataset, &data))
+    }
+
+    fn index_basic(&self, index_codes: &[String]) -> Result<Vec<IndexBasic>, Box<dyn Error>> {
+        crawl_index_basic(self, index_codes)
+    }
+
+    fn fund_basic(&self) -> Result<Vec<FundBasic>, Box<dyn Error>> {
+        crawl_fund_basic(self)
+    }
+}
+
+// open and closed days of one exchange, HKEX is from hk_tradecal which has no exchange field
+fn crawl_trade_calendar(
+    client: &TushareClient,
+    exchange: &str,
+    start_date: &str,
+    end_date: &str,
+) -> Result<TradingCalendar, Box<dyn std::error::Error>> {
+    let mut params: HashMap<String, String> = HashMap::new();
+    params.insert("start_date".to_owned(), start_date.to_owned());
+    params.insert("end_date".to_owned(), end_date.to_owned());
+    let items = if exchange == "HKEX" {
+        client
+            .query("hk_tradecal", params, "cal_date,is_open,pretrade_date")?
+            .items
+    } else {
+        params.insert("exchange".to_owned(), exchange.to_owned());
+        client
+            .query(
+                "trade_cal",
+                params,
+                "exchange,cal_date,is_open,pretrade_date",
+            )?
+            .items
+            .into_iter()
+            .map(|i| i[1..].to_vec())
+            .collect()
+    };
+
+    let mut days: Vec<CalendarDay> = Vec::new();
+    for i in items.iter() {
+        // is_open is a number, older responses have it as a string
+        let is_open = i[1]
+            .as_i64()
+            .or_else(|| i[1].as_str().and_then(|s| s.parse().ok()));
+        match (i[0].as_str(), is_open) {
+            (Some(cal_date), Some(is_open)) => days.push(CalendarDay {
+                exchange: exchange.to_owned(),
+                cal_date: cal_date.to_owned(),
+                is_open: is_open == 1,
+                pretrade_date: i[2].as_str().map(|d| d.to_owned()),
+            }),
+            _ => return Err(format!("{} calendar item is error: {:?}", exchange, i).into()),
+        }
+    }
+
+    Ok(TradingCalendar::new(exchange, days))
+}
+
+// delisted stocks have no area, industry and so on
+fn crawl_stocks_basic(
+    client: &TushareClient,
+    market: &Market,
+    list_status: ListStatus,
+) -> Result<Vec<StockBasic>, Box<dyn std::error::Error>> {
+    let mut params: HashMap<String, String> = HashMap::new();
+    if let Some(exchange) = &market.exchange {
+        params.insert("exchange".to_owned(), exchange.to_owned());
+    }
+    if let Some(market) = &market.market {
+        params.insert("market".to_owned(), market.to_owned());
+    }
+    params.insert("list_status".to_owned(), list_status.code().to_owned());
+    let stocks_base_vec: Vec<StockBasic> = client
+        .query(
+            "stock_basic",
+            params,
+        "ts_code, symbol, name, area, industry, fullname, enname, cnspell, market, exchange, curr_type, list_status, list_date, delist_date, is_hs",
+        )?
+        .decode("stock_basic")?;
+
+    debug!(
+        "{} {} {} stocks",
+        market,
+        list_status.code(),
+        stocks_base_vec.len()
+    );
+
+    Ok(stocks_base_vec)
+}
+
+fn crawl_index_basic(
+    client: &TushareClient,
+    index_codes: &[String],
+) -> Result<Vec<IndexBasic>, Box<dyn std::error::Error>> {
+    let mut index_basic_vec: Vec<IndexBasic> = Vec::new();
+    for index_code in index_codes {
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert("ts_code".to_owned(), index_code.to_owned());
+        let data = client.query(
+            "index_basic",
+            params,
+            "ts_code, name, market, publisher, category, base_date, base_point, list_date",
+        )?;
+        if data.items.is_empty() {
+            warn!("no index {} in index_basic", index_code);
+        }
+        index_basic_vec.extend(data.decode::<IndexBasic>("index_basic")?);
+    }
+
+    Ok(index_basic_vec)
+}
+
+fn crawl_fund_basic(client: &TushareClient) -> Result<Vec<FundBasic>, Box<dyn std::error::Error>> {
+    // E is exchange listed, O is over the counter
+    let mut params: HashMap<String, String> = HashMap::new();
+    params.insert("market".to_owned(), "E".to_owned());
+    let fund_basic_vec: Vec<FundBasic> = client
+        .query(
+            "fund_basic",
+            params,
+            "ts_code, name, management, fund_type, invest_type, market, status, list_date, delist_date",
+        )?
+        .decode("fund_basic")?;
+
+    Ok(fund_basic_vec)
+}
+
+// one csv line by column name
+struct CsvRow {
+    file_name: PathBuf,
+    values: HashMap<String, String>,
+}
+
+impl CsvRow {
+    fn optional_text(&self, column: &str) -> Option<String> {
+        match self.values.get(column).map(|v| v.trim()) {
+            None | Some("") | Some("none") => None,
+            Some(value) => Some(value.to_owned()),
+        }
+    }
+
+    fn text(&self, column: &str) -> Result<String, String> {
+        self.optional_text(column)
+            .ok_or_else(|| format!("{:?} has no {}: {:?}", self.file_name, column, self.values))
+    }
+
+    fn text_or_default(&self, column: &str) -> String {
+        self.optional_text(column).unwrap_or_default()
+    }
+}
+
+fn read_csv(file_name: &Path) -> Result<Vec<CsvRow>, Box<dyn Error>> {
+    let content =
+        fs::read_to_string(file_name).map_err(|e| format!("read {:?} error: {}", file_name, e))?;
+    let mut lines = content.lines();
+    let columns: Vec<String> = lines
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches('\u{feff}')
+        .split(',')
+        .map(|c| c.trim().to_owned())
+        .collect();
+    Ok(lines
+        .filter(|l| !l.trim().is_empty())
+        .map(|line| CsvRow {
+            file_name: file_name.to_path_buf(),
+            values: columns
+                .iter()
+                .cloned()
+                .zip(line.split(',').map(|v| v.to_owned()))
+                .collect(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::dataset;
+
+    #[test]
+    fn test_csv_source() {
+        let dir = std::env::temp_dir().join("choose_some_test_csv_source");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(dir.join("daily")).unwrap();
+        fs::create_dir_all(dir.join("daily_basic")).unwrap();
+        fs::write(
+            dir.join("stocks.csv"),
+            "ts_code,name,exchange,market,list_status,list_date,delist_date\n\
+             000001.SZ,平安银行,SZSE,主板,L,19910403,\n\
+             300001.SZ,特锐德,SZSE,创业板,L,20091030,\n\
+             000003.SZ,PT金田A,SZSE,主板,D,19910703,20020614\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("trade_cal.csv"),
+            "exchange,cal_date,is_open,pretrade_date\n\
+             SSE,20210103,0,20201231\n\
+             SSE,20210104,1,20201231\n\
+             SZSE,20210104,1,20201231\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("daily").join("000001.SZ.csv"),
+            "trade_date,ts_code,open,high,low,close,pre_close,change,pct_chg,vol,amount\n\
+             20210104,000001.SZ,19.1,19.1,18.44,18.6,19.34,-0.74,-3.8263,1554216.43,2891682.312\n\
+             20210105,000001.SZ,18.4,18.48,17.8,18.17,18.6,-0.43,-2.3118,1821352.1,3284606.79\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("daily_basic").join("000001.SZ.csv"),
+            "ts_code,trade_date,close,turnover_rate,pe,total_share,float_share,free_share,total_mv,circ_mv,limit_status\n\
+             000001.SZ,20210104,18.6,0.8009,none,1940591.8198,1940557.2183,1016413.0,36094,36094,\n",
+        )
+        .unwrap();
+
+        let source = CsvSource::new(&dir).unwrap();
+        let stocks_basic = source
+            .stocks_basic(&Market::new("SZSE", "主板"), ListStatus::Listed)
+            .unwrap();
+        assert_eq!(stocks_basic.len(), 1);
+        assert_eq!(stocks_basic[0].ts_code, "000001.SZ");
+        assert_eq!(stocks_basic[0].delist_date, None);
+        let stocks_basic = source
+            .stocks_basic(&"SZSE".parse().unwrap(), ListStatus::Delisted)
+            .unwrap();
+        assert_eq!(stocks_basic[0].delist_date, Some(String::from("20020614")));
+
+        let calendar = source
+            .trade_calendar("SSE", "20210101", "20210131")
+            .unwrap();
+        assert_eq!(calendar.days().len(), 2);
+        assert!(calendar.is_open("20210104"));
+
+        let ts_codes = vec![String::from("000001.SZ"), String::from("000002.SZ")];
+        let daily = dataset("daily").unwrap();
+        let rows = source
+            .dataset_rows(daily, &ts_codes, "20210105", "20210131")
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        let row = rows[0].as_ref().unwrap();
+        assert_eq!(row[daily.column("close").unwrap()], "18.17");
+        let daily_basic = dataset("daily_basic").unwrap();
+        let rows = source
+            .dataset_rows(daily_basic, &ts_codes, "20210101", "20210131")
+            .unwrap();
+        let row = rows[0].as_ref().unwrap();
+        assert_eq!(row[daily_basic.column("pe").unwrap()], "none");
+        assert_eq!(row[daily_basic.column("limit_status").unwrap()], "none");
+        assert!(source
+            .dataset_rows(
+                dataset("adj_factor").unwrap(),
+                &ts_codes,
+                "20210101",
+                "20210131"
+            )
+            .is_err());
+        assert!(source.index_basic(&ts_codes).is_err());
+        assert!(source.fund_basic().is_err());
+
+        assert_eq!("tushare".parse::<Source>().unwrap(), Source::Tushare);
+        assert_eq!(
+            "csv:/tmp/data".parse::<Source>().unwrap(),
+            Source::Csv(String::from("/tmp/data"))
+        );
+        assert!("csv:".parse::<Source>().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+/// Tradability and price limits of one stock by trade date
+/// from suspend_data (suspend_d) and stk_limit_data (stk_limit) in a date dir,
+/// limit_status of daily_basic is often none, so limits are told by comparing with the close.
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use crate::dataset::{has_file, read_as};
+use crate::models::{StockDaily, StockLimit, StockSuspend};
+
+// prices are in 0.01 yuan
+const PRICE_TICK: f64 = 0.01;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ClosedAt {
+    UpLimit,
+    DownLimit,
+    Inside,
+}
+
+#[derive(Debug, Default)]
+pub struct TradingStatus {
+    // trade_date -> suspend_timing of suspended days
+    suspended: HashMap<String, Option<String>>,
+    limits: HashMap<String, StockLimit>,
+}
+
+impl TradingStatus {
+    pub fn new(suspends: Vec<StockSuspend>, limits: Vec<StockLimit>) -> TradingStatus {
+        TradingStatus {
+            suspended: suspends
+                .into_iter()
+                .filter(|s| s.suspend_type == "S")
+                .map(|s| (s.trade_date, s.suspend_timing))
+                .collect(),
+            limits: limits
+                .into_iter()
+                .map(|l| (l.trade_date.clone(), l))
+                .collect(),
+        }
+    }
+
+    /// one stock in date dir, a dataset not downloaded is empty
+    pub fn load(date_dir: &Path, ts_code: &str) -> Result<TradingStatus, Box<dyn Error>> {
+        let suspends: Vec<StockSuspend> = if has_file(date_dir, "suspend_d", ts_code) {
+            read_as(date_dir, "suspend_d", ts_code)?
+        } else {
+            vec![]
+        };
+        let limits: Vec<StockLimit> = if has_file(date_dir, "stk_limit", ts_code) {
+            read_as(date_dir, "stk_limit", ts_code)?
+        } else {
+            vec![]
+        };
+        Ok(TradingStatus::new(suspends, limits))
+    }
+
+    /// suspended all day
+    pub fn is_suspended(&self, trade_date: &str) -> bool {
+        matches!(self.suspended.get(trade_date), Some(None))
+    }
+
+    /// not suspended all day, a day suspended for some time can still be traded
+    pub fn is_tradable(&self, trade_date: &str) -> bool {
+        !self.is_suspended(trade_date)
+    }
+
+    pub fn limit(&self, trade_date: &str) -> Option<&StockLimit> {
+        self.limits.get(trade_date)
+    }
+
+    /// None if the limits of the day are unknown
+    pub fn closed_at(&self, bar: &StockDaily) -> Option<ClosedAt> {
+        let limit = self.limit(&bar.trade_date)?;
+        if (bar.close - limit.up_limit).abs() < PRICE_TICK / 2.0 {
+            Some(ClosedAt::UpLimit)
+        } else if (bar.close - limit.down_limit).abs() < PRICE_TICK / 2.0 {
+            Some(ClosedAt::DownLimit)
+        } else {
+            Some(ClosedAt::Inside)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trading_status() {
+        let suspend =
+            |trade_date: &str, suspend_timing: Option<&str>, suspend_type: &str| StockSuspend {
+                ts_code: String::from("000001.SZ"),
+                trade_date: trade_date.to_owned(),
+                suspend_timing: suspend_timing.map(|t| t.to_owned()),
+                suspend_type: suspend_type.to_owned(),
+            };
+        let suspends = vec![
+            suspend("20210105", None, "S"),
+            suspend("20210106", Some("09:30-10:30"), "S"),
+            suspend("20210107", None, "R"),
+        ];
+        let limits = vec![StockLimit {
+            ts_code: String::from("000001.SZ"),
+            trade_date: String::from("20210104"),
+            up_limit: 21.12,
+            down_limit: 17.28,
+        }];
+        let status = TradingStatus::new(suspends, limits);
+
+        assert!(status.is_tradable("20210104"));
+        assert!(status.is_suspended("20210105"));
+        assert!(status.is_tradable("20210106"));
+        assert!(status.is_tradable("20210107"));
+
+        let mut bar = StockDaily {
+            ts_code: String::from("000001.SZ"),
+            trade_date: String::from("20210104"),
+            open: 19.1,
+            high: 21.12,
+            low: 19.0,
+            close: 21.12,
+            pre_close: 19.2,
+            change: 1.92,
+            pct_chg: 10.0,
+            vol: 100.0,
+            amount: 1000.0,
+        };
+        assert_eq!(status.closed_at(&bar), Some(ClosedAt::UpLimit));
+        bar.close = 17.28;
+        assert_eq!(status.closed_at(&bar), Some(ClosedAt::DownLimit));
+        bar.close = 19.5;
+        assert_eq!(status.closed_at(&bar), Some(ClosedAt::Inside));
+        bar.trade_date = String::from("20210105");
+        assert_eq!(status.closed_at(&bar), None);
+    }
+
+    #[test]
+    fn test_load_without_data() {
+        let status = TradingStatus::load(Path::new("/nonexistent"), "000001.SZ").unwrap();
+        assert!(status.is_tradable("20210104"));
+        assert!(status.limit("20210104").is_none());
+    }
+}
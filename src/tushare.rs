@@ -0,0 +1,502 @@
+/// Tushare pro http client
+/// every api is one POST of TushareRESTfulAPI json to base url,
+/// response is {code, msg, request_id, data: {fields, items, has_more}}
+use log::{debug, error, warn};
+use serde::de::{Deserialize, DeserializeOwned, Deserializer};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::cassette::Cassette;
+use crate::models::TushareRESTfulAPI;
+use crate::rate_limit::RateLimiter;
+use crate::retry::RetryPolicy;
+use crate::Config;
+
+pub const DEFAULT_API_URL: &str = "http://api.waditu.com";
+
+// tushare returns at most PAGE_LIMIT rows every call and sets has_more if there are more,
+// so query page by page with offset and limit, and join all pages' items.
+const PAGE_LIMIT: usize = 5000;
+
+#[derive(Debug)]
+pub enum TushareError {
+    /// request not sent or response not read
+    Network(String, reqwest::Error),
+    /// http status is not 200
+    Status(String, u16),
+    /// too many calls per minute, wait and retry
+    RateLimited(String, i64, String),
+    /// tushare server error or busy, wait and retry
+    ServerBusy(String, i64, String),
+    /// token is wrong or expired
+    BadToken(String, i64, String),
+    /// no permission for the api, not enough points or daily quota used up
+    NoPermission(String, i64, String),
+    /// params or fields of the api are wrong
+    InvalidParams(String, i64, String),
+    /// other api return code != 0
+    Api(String, i64, String),
+    /// response is not tushare json
+    Decode(String, String),
+    /// replaying a cassette without this call
+    NotRecorded(String, String),
+    /// one row doesn't fit its type, index of the row and why
+    Row(String, usize, String),
+}
+
+impl TushareError {
+    /// classify api return code != 0 by code and msg
+    pub fn from_code(api_name: &str, code: i64, msg: &str) -> TushareError {
+        let api_name = api_name.to_owned();
+        let msg_string = msg.to_owned();
+        if code == 40101 || msg.contains("token") {
+            TushareError::BadToken(api_name, code, msg_string)
+        } else if msg.contains("每分钟") || msg.contains("每小时") || msg.contains("频率") {
+            TushareError::RateLimited(api_name, code, msg_string)
+        } else if code == 40203 || msg.contains("权限") || msg.contains("积分") {
+            TushareError::NoPermission(api_name, code, msg_string)
+        } else if code == -2001 || code == 40001 || msg.contains("参数") {
+            TushareError::InvalidParams(api_name, code, msg_string)
+        } else if code == -1 || code >= 50000 || msg.contains("繁忙") || msg.contains("系统") {
+            TushareError::ServerBusy(api_name, code, msg_string)
+        } else {
+            TushareError::Api(api_name, code, msg_string)
+        }
+    }
+
+    /// network errors, non-200 status, rate limited and server busy are worth retrying
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TushareError::Network(_, _)
+            | TushareError::Status(_, _)
+            | TushareError::RateLimited(_, _, _)
+            | TushareError::ServerBusy(_, _, _) => true,
+            TushareError::BadToken(_, _, _)
+            | TushareError::NoPermission(_, _, _)
+            | TushareError::InvalidParams(_, _, _)
+            | TushareError::Api(_, _, _)
+            | TushareError::Decode(_, _)
+            | TushareError::NotRecorded(_, _)
+            | TushareError::Row(_, _, _) => false,
+        }
+    }
+}
+
+impl fmt::Display for TushareError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TushareError::Network(api_name, e) => {
+                write!(f, "get {} network error: {}", api_name, e)
+            }
+            TushareError::Status(api_name, status) => {
+                write!(f, "get {} res status NOT 200! {}", api_name, status)
+            }
+            TushareError::RateLimited(api_name, code, msg) => {
+                write!(
+                    f,
+                    "get {} rate limited, code: {}, msg: {}",
+                    api_name, code, msg
+                )
+            }
+            TushareError::ServerBusy(api_name, code, msg) => {
+                write!(
+                    f,
+                    "get {} server busy, code: {}, msg: {}",
+                    api_name, code, msg
+                )
+            }
+            TushareError::BadToken(api_name, code, msg) => {
+                write!(
+                    f,
+                    "get {} bad token, code: {}, msg: {}",
+                    api_name, code, msg
+                )
+            }
+            TushareError::NoPermission(api_name, code, msg) => write!(
+                f,
+                "get {} no permission or points, code: {}, msg: {}",
+                api_name, code, msg
+            ),
+            TushareError::InvalidParams(api_name, code, msg) => {
+                write!(
+                    f,
+                    "get {} invalid params, code: {}, msg: {}",
+                    api_name, code, msg
+                )
+            }
+            TushareError::Api(api_name, code, msg) => write!(
+                f,
+                "get {} api return code != 0, ={}, msg: {}",
+                api_name, code, msg
+            ),
+            TushareError::Decode(api_name, msg) => {
+                write!(f, "get {} decode response error: {}", api_name, msg)
+            }
+            TushareError::NotRecorded(api_name, params) => {
+                write!(f, "get {} not in cassette, params: {}", api_name, params)
+            }
+            TushareError::Row(api_name, index, msg) => {
+                write!(f, "get {} row {} decode error: {}", api_name, index, msg)
+            }
+        }
+    }
+}
+
+impl Error for TushareError {}
+
+/// one api result, items are rows in the same order as fields
+#[derive(Debug, Clone, Default)]
+pub struct TushareData {
+    pub fields: Vec<String>,
+    pub items: Vec<Vec<serde_json::Value>>,
+}
+
+impl TushareData {
+    /// every row as T by the returned field names, so the order of fields doesn't matter.
+    /// columns T has no field for are ignored, a missing column is None of an Option field.
+    /// a null or wrong typed cell of a non Option field fails only its row
+    pub fn rows<T: DeserializeOwned>(&self, api_name: &str) -> Vec<Result<T, TushareError>> {
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let row: serde_json::Map<String, serde_json::Value> = self
+                    .fields
+                    .iter()
+                    .cloned()
+                    .zip(item.iter().cloned())
+                    .collect();
+                serde_json::from_value(serde_json::Value::Object(row.clone())).map_err(|e| {
+                    TushareError::Row(
+                        api_name.to_owned(),
+                        index,
+                        format!("{}, row: {}", e, serde_json::Value::Object(row)),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// all rows as T, the first bad row is the error
+    pub fn decode<T: DeserializeOwned>(&self, api_name: &str) -> Result<Vec<T>, TushareError> {
+        self.rows(api_name).into_iter().collect()
+    }
+}
+
+/// serde deserialize_with of fields tushare may return as null, null is the default value
+pub fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+pub struct TushareClient {
+    token: String,
+    base_url: String,
+    client: reqwest::blocking::Client,
+    rate_limiter: Arc<RateLimiter>,
+    retry_policy: RetryPolicy,
+    cassette: Option<Arc<Cassette>>,
+}
+
+impl TushareClient {
+    pub fn new(
+        token: &str,
+        base_url: &str,
+        timeout: Duration,
+        rate_limiter: Arc<RateLimiter>,
+        retry_policy: RetryPolicy,
+    ) -> Result<TushareClient, Box<dyn Error>> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(timeout)
+            .connect_timeout(timeout)
+            .build()?;
+        Ok(TushareClient {
+            token: token.to_owned(),
+            base_url: base_url.to_owned(),
+            client,
+            rate_limiter,
+            retry_policy,
+            cassette: None,
+        })
+    }
+
+    /// record calls into the cassette, or answer them from it
+    pub fn with_cassette(mut self, cassette: Arc<Cassette>) -> TushareClient {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    pub fn from_config(config: &Config) -> Result<TushareClient, Box<dyn Error>> {
+        let client = TushareClient::new(
+            &config.tushare_token,
+            &config.tushare_api_url,
+            Duration::from_secs(config.request_timeout),
+            Arc::new(RateLimiter::new(
+                config.account_tier,
+                config.rate_limits.clone(),
+            )),
+            RetryPolicy::new(config.max_retries),
+        )?;
+        match &config.cassette {
+            Some(mode) => Ok(client.with_cassette(Arc::new(Cassette::open(mode)?))),
+            None => Ok(client),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// query all pages of one api
+    pub fn query(
+        &self,
+        api_name: &str,
+        params: HashMap<String, String>,
+        fields: &str,
+    ) -> Result<TushareData, TushareError> {
+        let mut all_data = TushareData::default();
+        let mut offset = 0;
+        loop {
+            let mut page_params = params.clone();
+            page_params.insert("offset".to_owned(), offset.to_string());
+            page_params.insert("limit".to_owned(), PAGE_LIMIT.to_string());
+            let (mut data, has_more) =
+                self.query_one_page_with_retry(api_name, page_params, fields)?;
+            let items_len = data.items.len();
+            if all_data.fields.is_empty() {
+                all_data.fields = data.fields;
+            }
+            all_data.items.append(&mut data.items);
+            if !has_more || items_len == 0 {
+                break;
+            }
+            offset += items_len;
+            debug!("{} has more, next offset: {}", api_name, offset);
+        }
+
+        Ok(all_data)
+    }
+
+    fn query_one_page_with_retry(
+        &self,
+        api_name: &str,
+        params: HashMap<String, String>,
+        fields: &str,
+    ) -> Result<(TushareData, bool), TushareError> {
+        let mut attempt = 0;
+        loop {
+            match self.query_one_page(api_name, params.clone(), fields) {
+                Ok(result) => return Ok(result),
+                Err(e) if e.is_retryable() && attempt < self.retry_policy.max_retries => {
+                    let delay = self.retry_policy.delay(attempt);
+                    attempt += 1;
+                    warn!(
+                        "retryable: {}, retry {}/{} after {:?}",
+                        e, attempt, self.retry_policy.max_retries, delay
+                    );
+                    thread::sleep(delay);
+                }
+                Err(e) => {
+                    if e.is_retryable() {
+                        error!("retryable but gave up after {} retries: {}", attempt, e);
+                    } else {
+                        error!("fatal: {}", e);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn query_one_page(
+        &self,
+        api_name: &str,
+        params: HashMap<String, String>,
+        fields: &str,
+    ) -> Result<(TushareData, bool), TushareError> {
+        let api_params = TushareRESTfulAPI {
+            api_name: api_name.to_owned(),
+            token: self.token.to_owned(),
+            params,
+            fields: fields.to_owned(),
+        };
+
+        let api_params_json = serde_json::to_string(&api_params)
+            .map_err(|e| TushareError::Decode(api_name.to_owned(), e.to_string()))?;
+        debug!("{} {:?}", api_name, api_params.params);
+
+        if let Some(cassette) = self.cassette.as_ref().filter(|c| c.is_replay()) {
+            return match cassette.replay(api_name, &api_params.params, fields) {
+                Some(res_text_str) => decode_response(api_name, &res_text_str),
+                None => Err(TushareError::NotRecorded(
+                    api_name.to_owned(),
+                    format!("{:?}, fields: {}", api_params.params, fields),
+                )),
+            };
+        }
+
+        self.rate_limiter.acquire(api_name);
+
+        let res = self
+            .client
+            .post(&self.base_url)
+            .body(api_params_json)
+            .send()
+            .map_err(|e| TushareError::Network(api_name.to_owned(), e))?;
+
+        if !res.status().is_success() {
+            return Err(TushareError::Status(
+                api_name.to_owned(),
+                res.status().as_u16(),
+            ));
+        }
+
+        let res_text_str = res
+            .text()
+            .map_err(|e| TushareError::Network(api_name.to_owned(), e))?;
+        if let Some(cassette) = &self.cassette {
+            if let Err(e) = cassette.record(
+                &self.token,
+                api_name,
+                &api_params.params,
+                fields,
+                &res_text_str,
+            ) {
+                warn!("record {} into cassette error: {}", api_name, e);
+            }
+        }
+        decode_response(api_name, &res_text_str)
+    }
+}
+
+/// decode response body into fields and items, and has_more
+pub fn decode_response(
+    api_name: &str,
+    res_text_str: &str,
+) -> Result<(TushareData, bool), TushareError> {
+    let api_res: serde_json::Value = serde_json::from_str(res_text_str)
+        .map_err(|e| TushareError::Decode(api_name.to_owned(), e.to_string()))?;
+    let code = match api_res["code"].as_i64() {
+        Some(code) => code,
+        None => {
+            return Err(TushareError::Decode(
+                api_name.to_owned(),
+                format!("no code, request_id: {}", api_res["request_id"]),
+            ))
+        }
+    };
+    if code != 0 {
+        debug!("{} request_id: {}", api_name, api_res["request_id"]);
+        return Err(TushareError::from_code(
+            api_name,
+            code,
+            api_res["msg"].as_str().unwrap_or_default(),
+        ));
+    }
+
+    let api_data = &api_res["data"];
+    let has_more = api_data["has_more"] == true;
+    let fields: Vec<String> = match api_data["fields"].as_array() {
+        Some(fields) => fields
+            .iter()
+            .map(|f| f.as_str().unwrap_or_default().to_owned())
+            .collect(),
+        None => vec![],
+    };
+    let mut items: Vec<Vec<serde_json::Value>> = vec![];
+    if let Some(rows) = api_data["items"].as_array() {
+        for row in rows {
+            match row.as_array() {
+                Some(row) => items.push(row.clone()),
+                None => {
+                    return Err(TushareError::Decode(
+                        api_name.to_owned(),
+                        format!("item is not array: {}", row),
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok((TushareData { fields, items }, has_more))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_response() {
+        let body = r#"{"request_id":"1","code":0,"msg":"","data":{"fields":["exchange","cal_date","is_open"],"items":[["SSE","20210901",1],["SSE","20210902",1]],"has_more":true}}"#;
+        let (data, has_more) = decode_response("trade_cal", body).unwrap();
+        assert!(has_more);
+        assert_eq!(data.fields, vec!["exchange", "cal_date", "is_open"]);
+        assert_eq!(data.items.len(), 2);
+        assert_eq!(data.items[1][1], "20210902");
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Row {
+        ts_code: String,
+        close: f64,
+        pe: Option<f64>,
+        #[serde(default, deserialize_with = "null_as_default")]
+        name: String,
+    }
+
+    #[test]
+    fn test_decode_rows_by_fields() {
+        let body = r#"{"request_id":"1","code":0,"msg":"","data":{"fields":["close","extra","name","ts_code"],"items":[[10.5,1,null,"000001.SZ"],[null,1,"b","000002.SZ"],["x",1,"c","000003.SZ"]],"has_more":false}}"#;
+        let (data, _) = decode_response("daily", body).unwrap();
+        let rows = data.rows::<Row>("daily");
+        assert_eq!(
+            rows[0].as_ref().unwrap(),
+            &Row {
+                ts_code: String::from("000001.SZ"),
+                close: 10.5,
+                pe: None,
+                name: String::new(),
+            }
+        );
+        assert!(matches!(rows[1], Err(TushareError::Row(_, 1, _))));
+        assert!(matches!(rows[2], Err(TushareError::Row(_, 2, _))));
+        let e = data.decode::<Row>("daily").unwrap_err();
+        assert!(e.to_string().contains("000002.SZ"));
+        assert!(!e.is_retryable());
+    }
+
+    #[test]
+    fn test_decode_response_code_error() {
+        let body = r#"{"request_id":"1","code":40101,"msg":"token error","data":null}"#;
+        let e = decode_response("trade_cal", body).unwrap_err();
+        assert!(matches!(e, TushareError::BadToken(_, 40101, _)));
+        assert!(!e.is_retryable());
+    }
+
+    #[test]
+    fn test_error_from_code() {
+        let e = TushareError::from_code("daily", 40203, "抱歉，您每分钟最多访问该接口500次");
+        assert!(matches!(e, TushareError::RateLimited(_, _, _)));
+        assert!(e.is_retryable());
+        let e = TushareError::from_code("daily", 40203, "抱歉，您没有访问该接口的权限");
+        assert!(matches!(e, TushareError::NoPermission(_, _, _)));
+        assert!(!e.is_retryable());
+        let e = TushareError::from_code("daily", -2001, "参数错误");
+        assert!(matches!(e, TushareError::InvalidParams(_, _, _)));
+        let e = TushareError::from_code("daily", -1, "系统内部错误");
+        assert!(e.is_retryable());
+        let e = TushareError::from_code("daily", 1, "unknown");
+        assert!(!e.is_retryable());
+    }
+}
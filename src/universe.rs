@@ -0,0 +1,148 @@
+/// Stock universe of one download, exchange and market pairs of stock_basic
+/// like "SSE:主板,SZSE:创业板", "SZSE" for every board of one exchange,
+/// "all" for every board of every exchange.
+/// it is written to the date dir as file "universe", so analysis knows what the data covers.
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const UNIVERSE_FILE: &str = "universe";
+pub const DEFAULT_MARKETS: &str = "SSE:主板,SZSE:主板";
+
+/// one stock_basic query, None means no filter
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Market {
+    pub exchange: Option<String>,
+    pub market: Option<String>,
+}
+
+impl Market {
+    pub fn new(exchange: &str, market: &str) -> Market {
+        Market {
+            exchange: Some(exchange.to_owned()),
+            market: Some(market.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for Market {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.exchange, &self.market) {
+            (None, _) => write!(f, "all"),
+            (Some(exchange), None) => write!(f, "{}", exchange),
+            (Some(exchange), Some(market)) => write!(f, "{}:{}", exchange, market),
+        }
+    }
+}
+
+impl FromStr for Market {
+    type Err = crate::ParseError;
+    fn from_str(a_str: &str) -> Result<Self, Self::Err> {
+        let a_str = a_str.trim();
+        if a_str == "all" {
+            return Ok(Market {
+                exchange: None,
+                market: None,
+            });
+        }
+        let mut kv = a_str.splitn(2, ':');
+        let exchange = kv.next().unwrap_or_default().trim();
+        let market = kv.next().map(|m| m.trim());
+        if exchange.is_empty() || market == Some("") {
+            return Err(
+                "Could not parse markets, should be like SSE:主板,SZSE:创业板, SZSE or all",
+            );
+        }
+        Ok(Market {
+            exchange: Some(exchange.to_owned()),
+            market: market.map(|m| m.to_owned()),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Universe {
+    pub markets: Vec<Market>,
+}
+
+impl Universe {
+    /// "all" covers every other market, so it is the only one left
+    pub fn new(markets: Vec<Market>) -> Universe {
+        if let Some(all) = markets.iter().find(|m| m.exchange.is_none()) {
+            return Universe {
+                markets: vec![all.clone()],
+            };
+        }
+        let mut unique: Vec<Market> = vec![];
+        for market in markets {
+            if !unique.contains(&market) {
+                unique.push(market);
+            }
+        }
+        Universe { markets: unique }
+    }
+
+    /// universe of the data in date dir, None if not recorded
+    pub fn read(date_dir: &Path) -> Option<Universe> {
+        let content = fs::read_to_string(date_dir.join(UNIVERSE_FILE)).ok()?;
+        content.lines().next()?.parse().ok()
+    }
+
+    pub fn write(&self, date_dir: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(date_dir.join(UNIVERSE_FILE), format!("{}\n", self))?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Universe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let markets: Vec<String> = self.markets.iter().map(|m| m.to_string()).collect();
+        write!(f, "{}", markets.join(","))
+    }
+}
+
+impl FromStr for Universe {
+    type Err = crate::ParseError;
+    fn from_str(a_str: &str) -> Result<Self, Self::Err> {
+        let mut markets: Vec<Market> = vec![];
+        for a_market in a_str.split(',').filter(|m| !m.trim().is_empty()) {
+            markets.push(a_market.parse()?);
+        }
+        if markets.is_empty() {
+            return Err("Could not parse markets, no market");
+        }
+        Ok(Universe::new(markets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_universe() {
+        let universe: Universe = "SSE:主板, SZSE:创业板,SZSE,SSE:主板".parse().unwrap();
+        assert_eq!(
+            universe.markets,
+            vec![
+                Market::new("SSE", "主板"),
+                Market::new("SZSE", "创业板"),
+                Market {
+                    exchange: Some("SZSE".to_owned()),
+                    market: None,
+                },
+            ]
+        );
+        assert_eq!(universe.to_string(), "SSE:主板,SZSE:创业板,SZSE");
+        assert_eq!(universe.to_string().parse::<Universe>().unwrap(), universe);
+
+        let universe: Universe = "SSE:主板,all".parse().unwrap();
+        assert_eq!(universe.to_string(), "all");
+
+        assert!("".parse::<Universe>().is_err());
+        assert!(":主板".parse::<Universe>().is_err());
+        assert!("SSE:".parse::<Universe>().is_err());
+    }
+}
@@ -0,0 +1,398 @@
+/// Validation of a downloaded date dir before it is marked _SUCCESS
+/// every stock of daily_data is checked against itself and the other datasets:
+/// ohlc: low <= open, close <= high.
+/// pre_close: pre_close is the close of the stock's previous bar, unless adj_factor changes
+/// or a dividend goes ex on the day.
+/// pct_chg: pct_chg is (close - pre_close) / pre_close in percent.
+/// missing_day: an open day of the calendar between the first and the last bar has no bar
+/// and is not a suspended day of suspend_data.
+/// close_mismatch: close of daily_basic is not close of daily.
+/// a check needing a dataset not downloaded is skipped: pre_close without adj_factor or dividend,
+/// missing_day without suspend_d or the calendar. the report is validation.json of the date dir,
+/// counts by check, stocks skipped by check and every issue.
+use crate::bars;
+use crate::calendar::TradingCalendar;
+use crate::dataset;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+pub const REPORT_FILE: &str = "validation.json";
+
+// prices have 2 decimals, pct_chg is rounded by tushare
+const PRICE_TOLERANCE: f64 = 0.001;
+const PCT_CHG_TOLERANCE: f64 = 0.01;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Issue {
+    pub check: &'static str,
+    pub ts_code: String,
+    pub trade_date: String,
+    pub detail: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+pub struct Report {
+    pub stocks: usize,
+    pub bars: usize,
+    pub passed: bool,
+    pub counts: BTreeMap<&'static str, usize>,
+    // stocks a check is skipped for, a dataset it needs is not downloaded
+    pub skipped: BTreeMap<&'static str, usize>,
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    fn add(&mut self, check: &'static str, ts_code: &str, trade_date: &str, detail: String) {
+        *self.counts.entry(check).or_insert(0) += 1;
+        self.issues.push(Issue {
+            check,
+            ts_code: ts_code.to_owned(),
+            trade_date: trade_date.to_owned(),
+            detail,
+        });
+    }
+
+    fn skip(&mut self, check: &'static str) {
+        *self.skipped.entry(check).or_insert(0) += 1;
+    }
+
+    pub fn write(&self, date_dir: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(
+            date_dir.join(REPORT_FILE),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// counts like ohlc 1, pct_chg 2
+    pub fn summary(&self) -> String {
+        let join = |counts: &BTreeMap<&'static str, usize>| {
+            if counts.is_empty() {
+                return String::from("none");
+            }
+            counts
+                .iter()
+                .map(|(check, count)| format!("{} {}", check, count))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        format!(
+            "{} stocks {} bars, issues: {}, skipped stocks: {}",
+            self.stocks,
+            self.bars,
+            join(&self.counts),
+            join(&self.skipped)
+        )
+    }
+}
+
+// records of one dataset file by trade date, None if the dataset has no file of the stock
+fn records_by_date(
+    date_dir: &Path,
+    name: &str,
+    ts_code: &str,
+    date_field: &str,
+) -> Result<Option<HashMap<String, dataset::Record>>, Box<dyn Error>> {
+    let dataset = match dataset::dataset(name) {
+        Some(dataset) => dataset,
+        None => return Ok(None),
+    };
+    if !date_dir.join(dataset.dir).join(ts_code).exists() {
+        return Ok(None);
+    }
+    Ok(Some(
+        dataset::read_records(date_dir, name, ts_code)?
+            .into_iter()
+            .filter_map(|r| Some((r.text(date_field)?.to_owned(), r)))
+            .collect(),
+    ))
+}
+
+/// check every stock of daily_data in date dir
+pub fn validate(date_dir: &Path) -> Result<Report, Box<dyn Error>> {
+    let mut report = Report::default();
+    let daily_dir = date_dir.join("daily_data");
+    let mut ts_codes: Vec<String> = vec![];
+    if daily_dir.exists() {
+        for entry in fs::read_dir(&daily_dir)? {
+            let ts_code = entry?.file_name().to_string_lossy().to_string();
+            // temp file of an interrupted write
+            if !ts_code.starts_with('.') {
+                ts_codes.push(ts_code);
+            }
+        }
+    }
+    ts_codes.sort();
+    // SSE and SZSE open on the same days
+    let calendar = TradingCalendar::load(date_dir, "SSE").ok();
+
+    for ts_code in &ts_codes {
+        let bars = bars::read_daily(date_dir, ts_code)?;
+        report.stocks += 1;
+        report.bars += bars.len();
+        let adj_factors = records_by_date(date_dir, "adj_factor", ts_code, "trade_date")?;
+        let ex_dates = records_by_date(date_dir, "dividend", ts_code, "ex_date")?;
+        let daily_basic = records_by_date(date_dir, "daily_basic", ts_code, "trade_date")?;
+        let suspends = records_by_date(date_dir, "suspend_d", ts_code, "trade_date")?;
+        let adj_factor = |adj_factors: &HashMap<String, dataset::Record>, trade_date: &str| {
+            adj_factors
+                .get(trade_date)
+                .and_then(|r| r.value("adj_factor"))
+        };
+        // without them every ex-right day looks like a wrong pre_close
+        let adjustments = adj_factors.as_ref().zip(ex_dates.as_ref());
+        if adjustments.is_none() {
+            report.skip("pre_close");
+        }
+
+        for (i, bar) in bars.iter().enumerate() {
+            let date = bar.trade_date.as_str();
+            if bar.low > bar.open.min(bar.close) + PRICE_TOLERANCE
+                || bar.high < bar.open.max(bar.close) - PRICE_TOLERANCE
+            {
+                report.add(
+                    "ohlc",
+                    ts_code,
+                    date,
+                    format!(
+                        "open {} high {} low {} close {}",
+                        bar.open, bar.high, bar.low, bar.close
+                    ),
+                );
+            }
+
+            if bar.pre_close > 0.0 {
+                let pct_chg = (bar.close - bar.pre_close) / bar.pre_close * 100.0;
+                if (pct_chg - bar.pct_chg).abs() > PCT_CHG_TOLERANCE {
+                    report.add(
+                        "pct_chg",
+                        ts_code,
+                        date,
+                        format!(
+                            "pct_chg {} but close and pre_close give {:.4}",
+                            bar.pct_chg, pct_chg
+                        ),
+                    );
+                }
+            }
+
+            if let (Some(prev), Some((adj_factors, ex_dates))) =
+                (i.checked_sub(1).map(|j| &bars[j]), adjustments)
+            {
+                // a missing factor on either day tells nothing about an adjustment
+                let factor_changed = match (
+                    adj_factor(adj_factors, date),
+                    adj_factor(adj_factors, &prev.trade_date),
+                ) {
+                    (Some(factor), Some(prev_factor)) => factor != prev_factor,
+                    _ => false,
+                };
+                let adjusted = factor_changed || ex_dates.contains_key(date);
+                if (bar.pre_close - prev.close).abs() > PRICE_TOLERANCE && !adjusted {
+                    report.add(
+                        "pre_close",
+                        ts_code,
+                        date,
+                        format!(
+                            "pre_close {} but close of {} is {}",
+                            bar.pre_close, prev.trade_date, prev.close
+                        ),
+                    );
+                }
+            }
+
+            if let Some(basic_close) = daily_basic
+                .as_ref()
+                .and_then(|d| d.get(date))
+                .and_then(|r| r.value("close"))
+            {
+                if (basic_close - bar.close).abs() > PRICE_TOLERANCE {
+                    report.add(
+                        "close_mismatch",
+                        ts_code,
+                        date,
+                        format!(
+                            "daily close {} daily_basic close {}",
+                            bar.close, basic_close
+                        ),
+                    );
+                }
+            }
+        }
+
+        // without them every suspended day looks missing
+        if calendar.is_none() || suspends.is_none() {
+            report.skip("missing_day");
+        }
+        if let (Some(calendar), Some(suspends), Some(first), Some(last)) =
+            (&calendar, &suspends, bars.first(), bars.last())
+        {
+            let bar_dates: HashSet<&str> = bars.iter().map(|b| b.trade_date.as_str()).collect();
+            for date in calendar.open_days(&first.trade_date, &last.trade_date) {
+                let suspended = suspends
+                    .get(date)
+                    .is_some_and(|r| r.text("suspend_type") == Some("S"));
+                if !bar_dates.contains(date) && !suspended {
+                    report.add(
+                        "missing_day",
+                        ts_code,
+                        date,
+                        String::from("open day without bar, not suspended"),
+                    );
+                }
+            }
+        }
+    }
+
+    report.passed = report.issues.is_empty();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::{calendar_file, CALENDAR_HEADER};
+
+    #[test]
+    fn test_validate() {
+        let date_dir = std::env::temp_dir().join("choose_some_test_validate");
+        if date_dir.exists() {
+            fs::remove_dir_all(&date_dir).unwrap();
+        }
+        for dir in &[
+            "daily_data",
+            "daily_basic_data",
+            "adj_factor_data",
+            "dividend_data",
+            "suspend_data",
+            "trade_cal",
+        ] {
+            fs::create_dir_all(date_dir.join(dir)).unwrap();
+        }
+        let daily_header = dataset::dataset("daily").unwrap().header();
+        // 0105 has low over close, 0107 is missing, 0108 is ex-date, 0111 has a wrong pre_close,
+        // 0112 has a wrong pct_chg, 0113 is suspended, 0114 has a wrong pre_close and no adj_factor
+        let daily = [
+            "000001.SZ\t20210104\t10\t10.5\t9.9\t10.2\t10\t0.2\t2\t100\t1000",
+            "000001.SZ\t20210105\t10.2\t10.6\t10.4\t10.3\t10.2\t0.1\t0.9804\t100\t1000",
+            "000001.SZ\t20210106\t10.3\t10.5\t10.1\t10.4\t10.3\t0.1\t0.9709\t100\t1000",
+            "000001.SZ\t20210108\t9.4\t9.6\t9.3\t9.5\t9.4\t0.1\t1.0638\t100\t1000",
+            "000001.SZ\t20210111\t9.5\t9.7\t9.4\t9.6\t9.4\t0.2\t2.1277\t100\t1000",
+            "000001.SZ\t20210112\t9.6\t9.8\t9.5\t9.7\t9.6\t0.1\t5\t100\t1000",
+            "000001.SZ\t20210114\t9.7\t9.9\t9.6\t9.8\t9.5\t0.3\t3.1579\t100\t1000",
+        ];
+        fs::write(
+            date_dir.join("daily_data").join("000001.SZ"),
+            format!("{}\n{}\n", daily_header, daily.join("\n")),
+        )
+        .unwrap();
+        fs::write(
+            date_dir.join("adj_factor_data").join("000001.SZ"),
+            "ts_code\ttrade_date\tadj_factor\n000001.SZ\t20210106\t1\n000001.SZ\t20210108\t1.1\n000001.SZ\t20210111\t1.1\n000001.SZ\t20210112\t1.1\n",
+        )
+        .unwrap();
+        // no dividend, the ex-date is told by adj_factor
+        fs::write(
+            date_dir.join("dividend_data").join("000001.SZ"),
+            format!("{}\n", dataset::dataset("dividend").unwrap().header()),
+        )
+        .unwrap();
+        fs::write(
+            date_dir.join("daily_basic_data").join("000001.SZ"),
+            "ts_code\ttrade_date\tclose\n000001.SZ\t20210104\t10.2\n000001.SZ\t20210106\t10.5\n",
+        )
+        .unwrap();
+        fs::write(
+            date_dir.join("suspend_data").join("000001.SZ"),
+            "ts_code\ttrade_date\tsuspend_timing\tsuspend_type\n000001.SZ\t20210113\tnone\tS\n",
+        )
+        .unwrap();
+        let days: Vec<String> = (4..=15)
+            .map(|d| {
+                let open = if d == 9 || d == 10 { 0 } else { 1 };
+                format!("SSE\t202101{:02}\t{}\tnone", d, open)
+            })
+            .collect();
+        fs::write(
+            calendar_file(&date_dir, "SSE"),
+            format!("{}\n{}\n", CALENDAR_HEADER, days.join("\n")),
+        )
+        .unwrap();
+
+        let report = validate(&date_dir).unwrap();
+        let found: Vec<(&str, &str)> = report
+            .issues
+            .iter()
+            .map(|i| (i.check, i.trade_date.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("ohlc", "20210105"),
+                ("close_mismatch", "20210106"),
+                ("pre_close", "20210111"),
+                ("pct_chg", "20210112"),
+                ("pre_close", "20210114"),
+                ("missing_day", "20210107"),
+            ]
+        );
+        assert_eq!((report.stocks, report.bars), (1, 7));
+        assert!(report.skipped.is_empty());
+        assert!(!report.passed);
+
+        report.write(&date_dir).unwrap();
+        let content = fs::read_to_string(date_dir.join(REPORT_FILE)).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json["counts"]["missing_day"], 1);
+        assert_eq!(json["issues"].as_array().unwrap().len(), 6);
+
+        fs::remove_dir_all(&date_dir).unwrap();
+    }
+    #[test]
+    fn test_validate_without_optional_datasets() {
+        let date_dir = std::env::temp_dir().join("choose_some_test_validate_without_optional");
+        if date_dir.exists() {
+            fs::remove_dir_all(&date_dir).unwrap();
+        }
+        for dir in &["daily_data", "trade_cal"] {
+            fs::create_dir_all(date_dir.join(dir)).unwrap();
+        }
+        // 0106 goes ex-right, 0107 is suspended, only daily and daily_basic are downloaded
+        let daily = [
+            "000001.SZ\t20210105\t10.2\t10.6\t10.1\t10.3\t10.2\t0.1\t0.9804\t100\t1000",
+            "000001.SZ\t20210106\t9.4\t9.6\t9.3\t9.5\t9.4\t0.1\t1.0638\t100\t1000",
+            "000001.SZ\t20210108\t9.5\t9.7\t9.4\t9.6\t9.5\t0.1\t1.0526\t100\t1000",
+        ];
+        fs::write(
+            date_dir.join("daily_data").join("000001.SZ"),
+            format!(
+                "{}\n{}\n",
+                dataset::dataset("daily").unwrap().header(),
+                daily.join("\n")
+            ),
+        )
+        .unwrap();
+        let days: Vec<String> = (5..=8)
+            .map(|d| format!("SSE\t202101{:02}\t1\tnone", d))
+            .collect();
+        fs::write(
+            calendar_file(&date_dir, "SSE"),
+            format!("{}\n{}\n", CALENDAR_HEADER, days.join("\n")),
+        )
+        .unwrap();
+
+        let report = validate(&date_dir).unwrap();
+        assert!(report.issues.is_empty());
+        assert!(report.passed);
+        assert_eq!(report.skipped.get("pre_close"), Some(&1));
+        assert_eq!(report.skipped.get("missing_day"), Some(&1));
+        assert!(report
+            .summary()
+            .ends_with("skipped stocks: missing_day 1, pre_close 1"));
+
+        fs::remove_dir_all(&date_dir).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+/// Download and analysis against bin/mock_tushare, like a real run without network:
+/// the mock listens on an ephemeral port and choose-some runs with --tushare-api-url.
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+// the mock is killed when the test ends, passed or not
+struct Mock(Child);
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// mock of some stocks and its url
+fn start_mock(stocks: usize) -> (Mock, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mock_tushare"))
+        .args(["--addr", "127.0.0.1:0", "--stocks", &stocks.to_string()])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("start mock_tushare");
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    let url = line.trim().rsplit(' ').next().expect("mock url").to_owned();
+    // the mock prints every request, keep its pipe drained
+    thread::spawn(move || {
+        let _ = stdout.read_to_end(&mut vec![]);
+    });
+    (Mock(child), url)
+}
+
+fn data_dir(name: &str) -> PathBuf {
+    let data_dir = env::temp_dir().join(name);
+    if data_dir.exists() {
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+    fs::create_dir_all(&data_dir).unwrap();
+    data_dir
+}
+
+#[test]
+fn test_download_and_analysis() {
+    let (_mock, url) = start_mock(4);
+    let data_dir = data_dir("choose_some_test_mock_pipeline");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_choose-some"))
+        .args([
+            "--tushare-api-url",
+            &url,
+            "-s",
+            "20210901",
+            "-e",
+            "20210910",
+            "--strict",
+        ])
+        .env("TUSHARE_TOKEN", "mock")
+        .env("DATA_DIR", &data_dir)
+        .env("RUST_LOG", "info")
+        .output()
+        .unwrap();
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", log);
+
+    // crawl::run
+    let date_dir = data_dir.join("20210910");
+    assert_eq!(
+        fs::read_to_string(date_dir.join("_SUCCESS")).unwrap(),
+        "daily\ndaily_basic"
+    );
+    let stocks_list = fs::read_to_string(date_dir.join("stocks_list")).unwrap();
+    let ts_codes: Vec<&str> = stocks_list
+        .lines()
+        .skip(1)
+        .map(|line| line.split('\t').next().unwrap())
+        .collect();
+    assert_eq!(ts_codes.len(), 4);
+    for dir in &["daily_data", "daily_basic_data"] {
+        for ts_code in &ts_codes {
+            let content = fs::read_to_string(date_dir.join(dir).join(ts_code)).unwrap();
+            // header and 8 trade days
+            assert_eq!(content.lines().count(), 9, "{} {}", dir, ts_code);
+        }
+    }
+
+    // analysis::run on the downloaded date dir
+    assert!(log.contains("analysis data of"), "{}", log);
+    assert!(log.contains("8 trading days"), "{}", log);
+
+    fs::remove_dir_all(&data_dir).unwrap();
+}